@@ -0,0 +1,39 @@
+use crate::lexer::Span;
+
+// Renders a message rustc-style, with the source line and a caret under the span:
+//
+// error: expected `;`, found `print`
+//  --> 1:10
+//   |
+// 1 | print(1) print(2);
+//   |          ^^^^^
+pub fn render(source: &str, span: Span, message: &str) -> String {
+    let line_number = span.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    let line = source.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+
+    // Tabs stay tabs so the caret lines up with the code above it
+    let padding: String = line
+        .chars()
+        .take(span.col.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    // Only underline the part of the span on this line, but at least one character
+    let width = source
+        .get(span.start..span.end)
+        .map_or(0, |text| text.chars().take_while(|&c| c != '\n').count())
+        .max(1);
+
+    format!(
+        "error: {}\n{}--> {}\n{} |\n{} | {}\n{} | {}{}\n",
+        message,
+        gutter,
+        span,
+        gutter,
+        line_number,
+        line,
+        gutter,
+        padding,
+        "^".repeat(width)
+    )
+}
@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::interpreter::Value;
+
+// The variables of one scope plus a link to the scope around it.
+#[derive(Default)]
+struct Scope {
+    values: RefCell<HashMap<String, Value>>,
+    parent: Option<Rc<Scope>>,
+}
+
+// A handle to the innermost scope. Blocks, loop bodies and function calls
+// push a new one on entry and pop it again when they are done, so their
+// variables disappear and shadowed outer ones come back. Cloning the handle
+// does not copy any variables.
+#[derive(Clone, Default)]
+pub struct Environment {
+    scope: Rc<Scope>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    fn find(&self, name: &str) -> Option<&Scope> {
+        let mut scope = &*self.scope;
+        while !scope.values.borrow().contains_key(name) {
+            scope = scope.parent.as_deref()?;
+        }
+        Some(scope)
+    }
+
+    // Looks `name` up from the innermost scope outwards.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.find(name)?.values.borrow().get(name).cloned()
+    }
+
+    // Whether `name` is declared in the innermost scope or one around it.
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    // Whether `name` is declared in the innermost scope itself, not just
+    // somewhere outside of it.
+    pub fn is_declared_here(&self, name: &str) -> bool {
+        self.scope.values.borrow().contains_key(name)
+    }
+
+    // Creates `name` in the innermost scope, shadowing a variable of the
+    // same name in an outer scope.
+    pub fn define(&mut self, name: String, value: Value) {
+        self.scope.values.borrow_mut().insert(name, value);
+    }
+
+    // Overwrites the nearest variable called `name`; does nothing if there
+    // is none.
+    pub fn assign(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.find(name) {
+            scope.values.borrow_mut().insert(name.to_string(), value);
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        *self = self.child();
+    }
+
+    // Leaves the innermost scope. The outermost (global) scope is never
+    // popped.
+    pub fn pop_scope(&mut self) {
+        if let Some(parent) = self.scope.parent.clone() {
+            self.scope = parent;
+        }
+    }
+
+    // A new, empty scope inside this one.
+    pub fn child(&self) -> Environment {
+        let scope = Scope { parent: Some(Rc::clone(&self.scope)), ..Scope::default() };
+        Environment { scope: Rc::new(scope) }
+    }
+
+    // The outermost scope, where a function call starts its own scope: the
+    // function sees the global variables, but not those of its caller.
+    pub fn globals(&self) -> Environment {
+        let mut scope = &self.scope;
+        while let Some(parent) = &scope.parent {
+            scope = parent;
+        }
+        Environment { scope: Rc::clone(scope) }
+    }
+}
+
+// Every variable of every scope, functions included, would be far too much
+// to read.
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Environment").finish_non_exhaustive()
+    }
+}
+
+// Two handles are equal if they point at the same scope.
+impl PartialEq for Environment {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.scope, &other.scope)
+    }
+}
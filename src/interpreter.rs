@@ -1,225 +1,922 @@
-use std::collections::HashMap;
-use crate::lexer::Token;
-use crate::parser::ASTNode;
-use crate::parser::{parse_if, parse_while};
-
-// Function to interpret the given AST node.
-pub fn interpret(ast: ASTNode, env: &mut HashMap<String, i64>) {
-    match ast {
-        // Variable assignment (e.g., `x = x - 1`)
-        ASTNode::Assignment { var_name, value } => {
-            let new_val = evaluate_expression(*value, env);
-            println!("Assigning value {} to variable {}", new_val, var_name);
-            env.insert(var_name.clone(), new_val);  // Update the variable in the environment
-        }
-
-        // Print statement
-        ASTNode::Print(expr) => {
-            let val = evaluate_expression(*expr, env);
-            println!("Print statement output: {}", val);  // Print the evaluated value
-        }
-
-        // If statement
-        ASTNode::If { condition, then_branch, else_branch } => {
-            let condition_result = evaluate_condition(*condition.clone(), env);
-            println!("Evaluating IF statement, condition: {:?}, result: {}", condition, condition_result);
-
-            if condition_result {
-                println!("Executing THEN branch of IF statement.");
-                interpret(*then_branch, env);
-            } else if let Some(else_branch) = else_branch {
-                println!("Executing ELSE branch of IF statement.");
-                interpret(*else_branch, env);
-            } else {
-                println!("Condition was false and no ELSE branch.");
-            }
-        }
-
-        // While statement
-        ASTNode::While { condition, body } => {
-            println!("Starting WHILE loop with condition: {:?}", condition);
-            while evaluate_condition(*condition.clone(), env) {
-                println!("WHILE loop condition is true. Current environment: {:?}", env);
-
-                // Interpret each statement inside the loop's body.
-                if let ASTNode::Block(ref statements) = *body.clone() {
-                    for statement in statements {
-                        println!("Executing statement in WHILE loop body: {:?}", statement);
-                        interpret(statement.clone(), env);
-                    }
-                } else {
-                    interpret(*body.clone(), env);
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::fmt;
+use std::io::Write;
+use crate::diagnostic;
+use crate::environment::Environment;
+use crate::lexer::{Span, Token};
+use crate::parser::{ASTNode, Program};
+use crate::stack::{StackGuard, DEFAULT_STACK_LIMIT};
+use crate::trace::{TraceEvent, Tracer};
+
+// A runtime value stored in variables and produced by expressions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    // Strings and lists are shared, so reading a variable or putting a list
+    // into another one does not copy their contents
+    Str(Rc<str>),
+    List(Rc<Vec<Value>>),
+    Nil,
+    Function {
+        name: String,
+        params: Vec<String>,
+        body: Rc<ASTNode>,  // shared with the declaration, so copying the value is cheap
+    },
+}
+
+impl Value {
+    // Name of the type as shown in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "str",
+            Value::List(_) => "list",
+            Value::Nil => "nil",
+            Value::Function { .. } => "function",
+        }
+    }
+
+    // Whether the value counts as true in an `if` or `while` condition:
+    // `false`, `nil`, zero and empty strings and lists are false.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Int(n) => *n != 0,
+            Value::Float(n) => *n != 0.0,
+            Value::Bool(b) => *b,
+            Value::Str(s) => !s.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            Value::Nil => false,
+            Value::Function { .. } => true,
+        }
+    }
+
+    // Equality as used by `==`, `!=` and `switch`. An int and a float are equal
+    // if the float is exactly that integer (`1 == 1.0`, but `2^53 + 1` is not
+    // the float `2^53` it would round to), lists compare element by element,
+    // and values of different types are never equal.
+    pub fn equals(&self, other: &Value) -> bool {
+        // The pairs still to compare. A loop rather than recursion, so lists
+        // nested however deep (`l = [l]` in a loop) cannot overflow the stack.
+        let mut pending = vec![(self, other)];
+        while let Some(pair) = pending.pop() {
+            let equal = match pair {
+                (Value::Int(l), Value::Float(r)) | (Value::Float(r), Value::Int(l)) => {
+                    // Floats from -2^63 up to but excluding 2^63 convert to i64 exactly
+                    let in_range = (i64::MIN as f64..-(i64::MIN as f64)).contains(r);
+                    r.fract() == 0.0 && in_range && *r as i64 == *l
                 }
+                (Value::List(l), Value::List(r)) => {
+                    pending.extend(l.iter().zip(r.iter()));
+                    l.len() == r.len()
+                }
+                (l, r) => l == r,
+            };
+            if !equal {
+                return false;
+            }
+        }
+        true
+    }
+}
 
-                // Re-evaluate the condition after each iteration.
-                if !evaluate_condition(*condition.clone(), env) {
-                    println!("Exiting WHILE loop, condition is now false.");
-                    break;
+// Lists nested however deep are taken apart with a loop instead of the
+// recursion of the default drop, which could overflow the stack. Only lists
+// no other value shares are taken apart; the others just lose a reference.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let Value::List(items) = self else { return };
+        let Some(items) = Rc::get_mut(items) else { return };
+        let mut pending = std::mem::take(items);
+        while let Some(mut value) = pending.pop() {
+            if let Value::List(items) = &mut value {
+                if let Some(items) = Rc::get_mut(items) {
+                    pending.append(items);
                 }
             }
-            println!("Exited WHILE loop.");
         }
+    }
+}
 
-        // Block of multiple statements
-        ASTNode::Block(statements) => {
-            println!("Executing block of statements.");
-            for statement in statements {
-                interpret(statement, env);
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The lists being written, with the elements still to come and whether
+        // one was written already. A loop rather than recursion, like `equals`.
+        let mut open: Vec<(std::slice::Iter<Value>, bool)> = Vec::new();
+        let mut value = self;
+        loop {
+            match value {
+                Value::Int(n) => write!(f, "{}", n)?,
+                Value::Float(n) => write!(f, "{:?}", n)?,  // `{:?}` keeps the `.0` of whole numbers
+                Value::Bool(b) => write!(f, "{}", b)?,
+                // Strings inside lists are quoted so `["1", 1]` stays readable
+                Value::Str(s) if !open.is_empty() => write!(f, "{:?}", s)?,
+                Value::Str(s) => write!(f, "{}", s)?,
+                Value::List(items) => {
+                    write!(f, "[")?;
+                    open.push((items.iter(), false));
+                }
+                Value::Nil => write!(f, "nil")?,
+                Value::Function { name, params, .. } => write!(f, "<fn {}({})>", name, params.join(", "))?,
             }
-            println!("Finished executing block.");
+            // Go on with the next element, closing every list that has none left
+            value = loop {
+                let Some((items, started)) = open.last_mut() else { return Ok(()) };
+                match items.next() {
+                    Some(item) => {
+                        if std::mem::replace(started, true) {
+                            write!(f, ", ")?;
+                        }
+                        break item;
+                    }
+                    None => {
+                        write!(f, "]")?;
+                        open.pop();
+                    }
+                }
+            };
         }
+    }
+}
 
-        // For loop
-        ASTNode::For {
-            iterator,
-            iterable,
-            body,
-        } => {
-            // Assume `iterator` is an `ASTNode::Identifier`
-            if let ASTNode::Identifier(var_name) = *iterator {
-                // Assume `iterable` is an `ASTNode::Range` with `start` and `end`
-                if let ASTNode::Range { start, end } = *iterable {
-                    let start_val = evaluate_expression(*start, env);
-                    let end_val = evaluate_expression(*end, env);
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorKind {
+    // Reading or assigning a variable that was never declared
+    UndefinedVariable,
+    // `var` or `fn` for a name already declared in the same scope
+    Redeclaration,
+    // Integer `/` or `%` with a zero divisor
+    DivisionByZero,
+    // An integer result outside of i64 with `OverflowMode::Checked`
+    IntegerOverflow,
+    // An operator or statement applied to values of the wrong types
+    TypeMismatch,
+    // A node that cannot be run where it appears, e.g. an `if` used as a value
+    Unsupported,
+    // A function called with the wrong number of arguments
+    ArityMismatch,
+    // Function calls nested deeper than `Interpreter::with_max_call_depth` allows,
+    // usually endless recursion, or a program that used up `with_stack_limit`
+    StackOverflow,
+    // `print` could not write to the output given with `Interpreter::with_output`
+    Output,
+}
 
-                    println!("For loop: iterating from {} to {}", start_val, end_val);
+// Why running a program stopped, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    pub span: Span,
+}
 
-                    // Loop over the range and update the iterator variable in the environment.
-                    for i in start_val..end_val {
-                        println!("For loop iteration: {} = {}", var_name, i);
-                        env.insert(var_name.clone(), i);
+impl RuntimeError {
+    fn new(kind: RuntimeErrorKind, span: Span, message: String) -> Self {
+        RuntimeError { kind, message, span }
+    }
 
-                        // Interpret the body of the `for` loop for each iteration.
-                        interpret(*body.clone(), env);
-                    }
+    // The error with the offending source line and a caret under it.
+    pub fn render(&self, source: &str) -> String {
+        diagnostic::render(source, self.span, &self.message)
+    }
+}
 
-                    // Remove the iterator from the environment after the loop finishes.
-                    env.remove(&var_name);
-                } else {
-                    println!("Error: Expected a range as the iterable in the `for` loop.");
-                }
-            } else {
-                println!("Error: Expected an identifier as the iterator in the `for` loop.");
-            }
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error at {}: {}", self.span, self.message)
+    }
+}
+
+// For nodes that are not expressions, e.g. an `if` used as a value.
+fn unsupported(span: Span) -> RuntimeError {
+    RuntimeError::new(RuntimeErrorKind::Unsupported, span, "this cannot be used as a value".to_string())
+}
+
+// An operator applied to values of the wrong types.
+fn type_error(span: Span, operator: &Token, left: &Value, right: &Value) -> RuntimeError {
+    RuntimeError::new(
+        RuntimeErrorKind::TypeMismatch,
+        span,
+        format!("cannot apply {} to {} and {}", operator.kind(), left.type_name(), right.type_name()),
+    )
+}
+
+// Numeric promotion for arithmetic operators: two ints stay ints (so `7 / 2`
+// is `3`), but as soon as one side is a float the other one is converted too
+// and the operation is done in f64 (`7 / 2.0` is `3.5`). Comparisons do not
+// promote, see `compare_int_float`.
+fn promote(left: Value, right: Value) -> (Value, Value) {
+    match (left, right) {
+        (Value::Int(l), Value::Float(r)) => (Value::Float(l as f64), Value::Float(r)),
+        (Value::Float(l), Value::Int(r)) => (Value::Float(l), Value::Float(r as f64)),
+        other => other,
+    }
+}
+
+// Orders an int and a float by their exact values, so ints above 2^53 do not
+// compare as the float they would round to (`2^53 + 1 > 2^53` also with the
+// right side written as a float). `None` if the float is NaN.
+fn compare_int_float(int: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    // -2^63 converts to i64 exactly, 2^63 is just out of range
+    if float >= -(i64::MIN as f64) {
+        return Some(Ordering::Less);
+    }
+    if float < i64::MIN as f64 {
+        return Some(Ordering::Greater);
+    }
+    let whole = float.trunc();
+    // Equal whole parts: the fraction decides, e.g. `2 < 2.5` and `-2 > -2.5`
+    Some(int.cmp(&(whole as i64)).then(0.0.partial_cmp(&(float - whole))?))
+}
+
+// What integer arithmetic does when the result does not fit into an i64.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OverflowMode {
+    // Stop with an `IntegerOverflow` error
+    #[default]
+    Checked,
+    // Wrap around in two's complement (`i64::MAX + 1` is `i64::MIN`)
+    Wrapping,
+    // Clamp to `i64::MIN`/`i64::MAX`
+    Saturating,
+}
+
+// How a statement finished: normally, or by a `return`, `break` or `continue`
+// that has to unwind the enclosing blocks up to the function call, loop or
+// `switch` it belongs to.
+#[derive(Debug, Clone, PartialEq)]
+enum Flow {
+    Normal,
+    Return(Value),
+    Break(Option<String>),  // with the label of the loop, if one was given
+    Continue(Option<String>),
+}
+
+// Whether a `break` or `continue` to `target` is meant for the loop labeled
+// `label`: without a target it is meant for the innermost loop.
+fn targets_loop(target: &Option<String>, label: &Option<String>) -> bool {
+    target.is_none() || target == label
+}
+
+// Default for `Interpreter::with_max_call_depth`: deep enough for ordinary
+// recursion, while endless recursion still stops quickly. Each call also
+// takes native stack, which `with_stack_limit` bounds separately.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// Runs parsed programs. Holds what the evaluation needs besides the
+// variables themselves, which are passed in as `env`.
+pub struct Interpreter<'a> {
+    tracer: Option<&'a mut dyn Tracer>,
+    output: Option<&'a mut dyn Write>,
+    overflow: OverflowMode,
+    max_call_depth: usize,
+    call_depth: usize,
+    depth: usize,  // statements and expressions we are in, see `enter`
+    stack: StackGuard,
+}
+
+impl Default for Interpreter<'_> {
+    fn default() -> Self {
+        Interpreter {
+            tracer: None,
+            output: None,
+            overflow: OverflowMode::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            call_depth: 0,
+            depth: 0,
+            stack: StackGuard::new(DEFAULT_STACK_LIMIT),
         }
+    }
+}
 
-        // Switch statement
-        ASTNode::Switch { expression, cases, default } => {
-            let expr_val = evaluate_expression(*expression, env);
-            println!("Evaluating SWITCH statement with expression value: {}", expr_val);
-            let mut matched = false;
+impl<'a> Interpreter<'a> {
+    pub fn new() -> Self {
+        Interpreter::default()
+    }
 
-            for (case_value, case_block) in cases {
-                let case_val = evaluate_expression(case_value, env);
-                println!("Comparing case value: {} with expression value: {}", case_val, expr_val);
+    // Reports evaluated expressions and variable reads/writes to `tracer`.
+    pub fn with_tracer(mut self, tracer: &'a mut dyn Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
 
-                if case_val == expr_val {
-                    matched = true;
-                    println!("Matched case value. Executing case block.");
-                    interpret(case_block, env);
-                    break;
-                }
+    // Sends what `print` writes to `output` instead of stdout.
+    pub fn with_output(mut self, output: &'a mut dyn Write) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    // How integer `+`, `-`, `*`, `/` and `%` handle overflow; `Checked` by default.
+    pub fn with_overflow(mut self, overflow: OverflowMode) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    // How deeply function calls may nest before running the program stops
+    // with a `StackOverflow` error; `DEFAULT_MAX_CALL_DEPTH` by default.
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    // How much native stack running a program may use, for calls as well as
+    // for deeply nested statements and expressions, before it stops with a
+    // `StackOverflow` error; `DEFAULT_STACK_LIMIT` by default. Above the
+    // default, run the interpreter on a thread with more stack than that.
+    pub fn with_stack_limit(mut self, limit: usize) -> Self {
+        self.stack.set_limit(limit);
+        self
+    }
+
+    // Integer arithmetic under the configured `OverflowMode`. The divisor is
+    // already known to be non-zero.
+    fn integer_arithmetic(&self, operator: &Token, l: i64, r: i64, span: Span) -> Result<Value, RuntimeError> {
+        let result = match (self.overflow, operator) {
+            // `i64::MIN % -1` only overflows on the way; the remainder itself
+            // is 0, so `%` never fails in any mode
+            (_, Token::Modulo) => Some(l.wrapping_rem(r)),
+            (OverflowMode::Checked, Token::Plus) => l.checked_add(r),
+            (OverflowMode::Checked, Token::Minus) => l.checked_sub(r),
+            (OverflowMode::Checked, Token::Multiply) => l.checked_mul(r),
+            (OverflowMode::Checked, _) => l.checked_div(r),
+            (OverflowMode::Wrapping, Token::Plus) => Some(l.wrapping_add(r)),
+            (OverflowMode::Wrapping, Token::Minus) => Some(l.wrapping_sub(r)),
+            (OverflowMode::Wrapping, Token::Multiply) => Some(l.wrapping_mul(r)),
+            (OverflowMode::Wrapping, _) => Some(l.wrapping_div(r)),
+            (OverflowMode::Saturating, Token::Plus) => Some(l.saturating_add(r)),
+            (OverflowMode::Saturating, Token::Minus) => Some(l.saturating_sub(r)),
+            (OverflowMode::Saturating, Token::Multiply) => Some(l.saturating_mul(r)),
+            (OverflowMode::Saturating, _) => Some(l.saturating_div(r)),
+        };
+        result.map(Value::Int).ok_or_else(|| {
+            RuntimeError::new(
+                RuntimeErrorKind::IntegerOverflow,
+                span,
+                format!("integer overflow in {} with {} and {}", operator.kind(), l, r),
+            )
+        })
+    }
+
+    // Program output: the value and a newline, nothing else.
+    fn print(&mut self, value: &Value, span: Span) -> Result<(), RuntimeError> {
+        let written = match self.output.as_mut() {
+            Some(output) => writeln!(output, "{}", value),
+            // Unlike `println!`, a closed stdout (e.g. `xene prog | head -1`) is an error, not a panic
+            None => writeln!(std::io::stdout().lock(), "{}", value),
+        };
+        written.map_err(|error| {
+            RuntimeError::new(RuntimeErrorKind::Output, span, format!("could not write output: {}", error))
+        })
+    }
+
+    fn trace(&mut self, event: TraceEvent) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&event);
+        }
+    }
+
+    fn lookup(&mut self, var_name: &str, span: Span, env: &Environment) -> Result<Value, RuntimeError> {
+        let Some(val) = env.get(var_name) else {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::UndefinedVariable,
+                span,
+                format!("undefined variable `{}`", var_name),
+            ));
+        };
+        self.trace(TraceEvent::Lookup { name: var_name, value: &val });
+        Ok(val)
+    }
+
+    // Updates the nearest variable called `name`; there has to be one.
+    fn assign(&mut self, env: &mut Environment, name: &str, value: Value, span: Span) -> Result<(), RuntimeError> {
+        if !env.contains(name) {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::UndefinedVariable,
+                span,
+                format!("cannot assign to undeclared variable `{}`", name),
+            ));
+        }
+        self.trace(TraceEvent::Assign { name, value: &value });
+        env.assign(name, value);
+        Ok(())
+    }
+
+    // Creates a variable in the innermost scope, shadowing outer ones.
+    fn define(&mut self, env: &mut Environment, name: String, value: Value) {
+        self.trace(TraceEvent::Assign { name: &name, value: &value });
+        env.define(name, value);
+    }
+
+    // Runs every top-level statement of a parsed program in order, stopping
+    // at the first runtime error.
+    pub fn interpret_program(&mut self, program: Program, env: &mut Environment) -> Result<(), RuntimeError> {
+        for statement in &program.statements {
+            self.execute(statement, env)?;
+        }
+        Ok(())
+    }
+
+    // Function to interpret the given AST node.
+    pub fn interpret(&mut self, ast: ASTNode, env: &mut Environment) -> Result<(), RuntimeError> {
+        self.execute(&ast, env)?;
+        Ok(())
+    }
+
+    // Function to evaluate expressions.
+    pub fn evaluate_expression(&mut self, expr: ASTNode, env: &mut Environment) -> Result<Value, RuntimeError> {
+        self.evaluate(&expr, env)
+    }
+
+    // Function to evaluate conditions (returns a boolean).
+    pub fn evaluate_condition(&mut self, condition: ASTNode, env: &mut Environment) -> Result<bool, RuntimeError> {
+        Ok(self.evaluate(&condition, env)?.is_truthy())
+    }
+
+    // Runs a statement and tells the enclosing statements whether to go on
+    // normally or to unwind, e.g. out of a function on `return`. The AST is
+    // only borrowed, so loops and calls run their bodies without copying
+    // them.
+    fn execute(&mut self, ast: &ASTNode, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        self.enter(ast.span())?;
+        let flow = self.execute_node(ast, env);
+        self.depth -= 1;
+        flow
+    }
+
+    // Every `execute` and `evaluate` goes one level deeper on the native
+    // stack, however the statements, expressions and calls are nested, so
+    // this is where the stack limit is checked.
+    fn enter(&mut self, span: Span) -> Result<(), RuntimeError> {
+        if self.depth == 0 {
+            self.stack.start();
+        } else if self.stack.exhausted() {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::StackOverflow,
+                span,
+                "nested too deeply, out of stack".to_string(),
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // `execute_node` and `evaluate_node` are on the stack once per nesting
+    // level, so they only dispatch: everything that needs more than a few
+    // locals happens in the methods they call, which keeps their frames small
+    // and recursion deep.
+    fn execute_node(&mut self, ast: &ASTNode, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        match ast {
+            // Variable declaration (e.g., `var x = 10;`)
+            ASTNode::VarDecl { var_name, value, span } => self.execute_var_decl(var_name, value, *span, env),
+
+            // Variable assignment (e.g., `x = x - 1`)
+            ASTNode::Assign { var_name, value, span } => self.execute_assign(var_name, value, *span, env),
+
+            // Print statement
+            ASTNode::Print(expr, span) => self.execute_print(expr, *span, env),
+
+            ASTNode::If { condition, then_branch, else_branch, .. } => {
+                self.execute_if(condition, then_branch, else_branch.as_deref(), env)
             }
 
-            if !matched {
-                if let Some(default_block) = default {
-                    println!("No case matched. Executing default block.");
-                    interpret(*default_block, env);
-                } else {
-                    println!("No case matched and no default block.");
-                }
+            ASTNode::While { condition, body, label, .. } => self.execute_while(condition, body, label, env),
+
+            // Block of multiple statements
+            ASTNode::Block(statements, _) => self.execute_block(statements, env),
+
+            ASTNode::For { iterator, iterable, body, label, span } => {
+                self.execute_for(iterator, iterable, body, label, *span, env)
+            }
+
+            ASTNode::Switch { expression, cases, default, .. } => {
+                self.execute_switch(expression, cases, default.as_deref(), env)
             }
+
+            ASTNode::Function { name, params, body, span } => self.execute_function(name, params, body, *span, env),
+
+            ASTNode::Return(value, _) => self.execute_return(value.as_deref(), env),
+
+            ASTNode::Break(label, _) => Ok(Flow::Break(label.clone())),
+
+            ASTNode::Continue(label, _) => Ok(Flow::Continue(label.clone())),
+
+            // Any other expression used as a statement is evaluated for its errors and effects
+            _ => self.execute_expression(ast, env),
+        }
+    }
+
+    fn execute_assign(&mut self, var_name: &str, value: &ASTNode, span: Span, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        let new_val = self.evaluate(value, env)?;
+        self.assign(env, var_name, new_val, span)?;  // Update the variable in the environment
+        Ok(Flow::Normal)
+    }
+
+    fn execute_print(&mut self, expr: &ASTNode, span: Span, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        let val = self.evaluate(expr, env)?;
+        self.print(&val, span)?;  // Print the evaluated value
+        Ok(Flow::Normal)
+    }
+
+    // A function declaration stores the function as a value under its name
+    fn execute_function(&mut self, name: &str, params: &[String], body: &Rc<ASTNode>, span: Span, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        if env.is_declared_here(name) {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::Redeclaration,
+                span,
+                format!("name `{}` is already declared in this scope", name),
+            ));
+        }
+        let function = Value::Function {
+            name: name.to_string(),
+            params: params.to_vec(),
+            body: Rc::clone(body),
+        };
+        self.define(env, name.to_string(), function);
+        Ok(Flow::Normal)
+    }
+
+    fn execute_return(&mut self, value: Option<&ASTNode>, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        let value = match value {
+            Some(value) => self.evaluate(value, env)?,
+            None => Value::Nil,
+        };
+        Ok(Flow::Return(value))
+    }
+
+    fn execute_expression(&mut self, expr: &ASTNode, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        self.evaluate(expr, env)?;
+        Ok(Flow::Normal)
+    }
+
+    fn execute_var_decl(&mut self, var_name: &str, value: &ASTNode, span: Span, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        let new_val = self.evaluate(value, env)?;
+        if env.is_declared_here(var_name) {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::Redeclaration,
+                span,
+                format!("variable `{}` is already declared in this scope", var_name),
+            ));
+        }
+        self.define(env, var_name.to_string(), new_val);
+        Ok(Flow::Normal)
+    }
+
+    fn execute_if(&mut self, condition: &ASTNode, then_branch: &ASTNode, else_branch: Option<&ASTNode>, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        if self.evaluate(condition, env)?.is_truthy() {
+            self.execute(then_branch, env)
+        } else if let Some(else_branch) = else_branch {
+            self.execute(else_branch, env)
+        } else {
+            Ok(Flow::Normal)
         }
-        ASTNode::List(list) => {
-            let evaluated_list: Vec<i64> = list.into_iter()
-                .map(|element| evaluate_expression(element, env))
-                .collect();
-            println!("Evaluated list: {:?}", evaluated_list);
+    }
+
+    // Calls `callee` with already evaluated arguments. The function body runs
+    // in a new scope inside the global one: it sees the global variables
+    // (including the function itself, for recursion) and its parameters, but
+    // not the caller's local variables, and its own variables are gone once
+    // it returns. Parameters and the body's variables share that scope, so
+    // `var` cannot redeclare a parameter.
+    fn call(&mut self, callee: Value, args: Vec<Value>, span: Span, env: &Environment) -> Result<Value, RuntimeError> {
+        let Value::Function { name, params, body } = &callee else {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                span,
+                format!("cannot call a value of type {}", callee.type_name()),
+            ));
+        };
+        self.check_arity(name, params.len(), args.len(), span)?;
+        self.check_call_depth(name, span)?;
+
+        let mut env = env.globals().child();
+        for (param, arg) in params.iter().zip(args) {
+            self.define(&mut env, param.clone(), arg);
         }
 
-        _ => {
-            println!("Unrecognized AST node: {:?}", ast);
+        // The parser always gives a function a block as its body
+        self.call_depth += 1;
+        let flow = match &**body {
+            ASTNode::Block(statements, _) => self.execute_statements(statements, &mut env),
+            body => self.execute(body, &mut env),
+        };
+        self.call_depth -= 1;
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            // Falling off the end returns `nil`. The parser keeps `break` and
+            // `continue` inside loops, so they never get this far.
+            Flow::Normal | Flow::Break(_) | Flow::Continue(_) => Ok(Value::Nil),
         }
     }
-}
 
-// Function to evaluate expressions.
-pub fn evaluate_expression(expr: ASTNode, env: &mut HashMap<String, i64>) -> i64 {
-    match expr {
-        ASTNode::Number(val) => val,
+    fn check_call_depth(&self, name: &str, span: Span) -> Result<(), RuntimeError> {
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::StackOverflow,
+                span,
+                format!("maximum call depth of {} exceeded in `{}`", self.max_call_depth, name),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_arity(&self, name: &str, params: usize, args: usize, span: Span) -> Result<(), RuntimeError> {
+        if args != params {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::ArityMismatch,
+                span,
+                format!("function `{}` expects {} argument(s), got {}", name, params, args),
+            ));
+        }
+        Ok(())
+    }
+
+    // Evaluates an expression and reports the result to the tracer.
+    fn evaluate(&mut self, expr: &ASTNode, env: &mut Environment) -> Result<Value, RuntimeError> {
+        self.enter(expr.span())?;
+        let value = self.evaluate_node(expr, env);
+        self.depth -= 1;
+        let value = value?;
+        self.trace(TraceEvent::Eval { span: expr.span(), value: &value });
+        Ok(value)
+    }
+
+    fn evaluate_node(&mut self, expr: &ASTNode, env: &mut Environment) -> Result<Value, RuntimeError> {
+        match expr {
+            ASTNode::Number(val, _) => Ok(Value::Int(*val)),
+
+            ASTNode::Float(val, _) => Ok(Value::Float(*val)),
+
+            ASTNode::Bool(val, _) => Ok(Value::Bool(*val)),
+
+            ASTNode::Str(text, _) => Ok(Value::Str(text.as_str().into())),
+
+            ASTNode::List(elements, _) => self.evaluate_list(elements, env),
+
+            ASTNode::Identifier(var_name, span) => self.lookup(var_name, *span, env),
 
-        ASTNode::Identifier(var_name) => {
-            if let Some(&val) = env.get(&var_name) {
-                println!("Retrieved value of variable {}: {}", var_name, val);
-                val
-            } else {
-                println!("Error: Undefined variable {}", var_name);
-                0 // Default to zero or handle this error differently.
+            ASTNode::Call { callee, args, span } => self.evaluate_call(callee, args, *span, env),
+
+            ASTNode::UnaryOp { operator, operand, span } => self.evaluate_unary(operator, operand, *span, env),
+
+            ASTNode::BinaryOp { left, operator, right, span } => self.evaluate_binary(left, operator, right, *span, env),
+
+            _ => Err(unsupported(expr.span())),
+        }
+    }
+
+    fn evaluate_list(&mut self, elements: &[ASTNode], env: &mut Environment) -> Result<Value, RuntimeError> {
+        Ok(Value::List(Rc::new(self.evaluate_all(elements, env)?)))
+    }
+
+    fn evaluate_call(&mut self, callee: &ASTNode, args: &[ASTNode], span: Span, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let callee = self.evaluate(callee, env)?;
+        let args = self.evaluate_all(args, env)?;
+        self.call(callee, args, span, env)
+    }
+
+    // A plain loop rather than `map` and `collect`, whose adapters would stay
+    // on the stack below every nested element in a debug build.
+    fn evaluate_all(&mut self, exprs: &[ASTNode], env: &mut Environment) -> Result<Vec<Value>, RuntimeError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            values.push(self.evaluate(expr, env)?);
+        }
+        Ok(values)
+    }
+
+    fn evaluate_unary(&mut self, operator: &Token, operand: &ASTNode, span: Span, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let value = self.evaluate(operand, env)?;
+        match operator {
+            Token::Not | Token::NotKeyword => Ok(Value::Bool(!value.is_truthy())),
+            _ => self.unary_operation(operator, value, span),
+        }
+    }
+
+    fn evaluate_binary(&mut self, left: &ASTNode, operator: &Token, right: &ASTNode, span: Span, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let left_val = self.evaluate(left, env)?;
+        // `&&` and `||` short-circuit: the right side is only evaluated if it decides the result
+        if let Token::And | Token::Or = operator {
+            let left_true = left_val.is_truthy();
+            if left_true == (*operator == Token::Or) {
+                return Ok(Value::Bool(left_true));
             }
+            return self.evaluate_truthy(right, env);
         }
+        let right_val = self.evaluate(right, env)?;
+        self.binary_operation(operator, left_val, right_val, span)
+    }
 
-        ASTNode::BinaryOp { left, operator, right } => {
-            let left_val = evaluate_expression(*left, env);
-            let right_val = evaluate_expression(*right, env);
+    fn evaluate_truthy(&mut self, expr: &ASTNode, env: &mut Environment) -> Result<Value, RuntimeError> {
+        Ok(Value::Bool(self.evaluate(expr, env)?.is_truthy()))
+    }
 
-            println!("Evaluating binary operation: {} {:?} {}", left_val, operator, right_val);
+    // A block is a scope of its own. It is left again even on an error, so the
+    // REPL can go on with the same environment.
+    fn execute_block(&mut self, statements: &[ASTNode], env: &mut Environment) -> Result<Flow, RuntimeError> {
+        env.push_scope();
+        let flow = self.execute_statements(statements, env);
+        env.pop_scope();
+        flow
+    }
 
-            match operator {
-                Token::Plus => left_val + right_val,
-                Token::Minus => left_val - right_val,
-                Token::Multiply => left_val * right_val,
-                Token::Divide => left_val / right_val,
-                _ => {
-                    println!("Error: Unknown operator {:?}", operator);
-                    0
-                }
+    fn execute_statements(&mut self, statements: &[ASTNode], env: &mut Environment) -> Result<Flow, RuntimeError> {
+        for statement in statements {
+            match self.execute(statement, env)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
             }
         }
+        Ok(Flow::Normal)
+    }
 
-        _ => {
-            println!("Error: Unsupported expression type {:?}", expr);
-            0
+    // A `break` or `continue` for an outer loop ends this one and is passed on.
+    fn execute_while(&mut self, condition: &ASTNode, body: &ASTNode, label: &Option<String>, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        while self.evaluate(condition, env)?.is_truthy() {
+            match self.execute(body, env)? {
+                Flow::Normal => {}
+                Flow::Continue(target) if targets_loop(&target, label) => {}
+                Flow::Break(target) if targets_loop(&target, label) => break,
+                flow => return Ok(flow),
+            }
         }
+        Ok(Flow::Normal)
     }
-}
 
-// Function to evaluate conditions (returns a boolean).
-pub fn evaluate_condition(condition: ASTNode, env: &mut HashMap<String, i64>) -> bool {
-    match condition {
-        ASTNode::Number(val) => val != 0,
+    fn execute_for(&mut self, iterator: &ASTNode, iterable: &ASTNode, body: &ASTNode, label: &Option<String>, span: Span, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        // The parser only produces an identifier here
+        let ASTNode::Identifier(var_name, _) = iterator else {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::Unsupported,
+                span,
+                "expected an identifier as the iterator in the `for` loop".to_string(),
+            ));
+        };
+        // Only ranges can be iterated so far
+        let ASTNode::Range { start, end, .. } = iterable else {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                iterable.span(),
+                "expected a range as the iterable in the `for` loop".to_string(),
+            ));
+        };
 
-        ASTNode::Identifier(var_name) => {
-            if let Some(&val) = env.get(&var_name) {
-                println!("Condition for {}: {}", var_name, val != 0);
-                val != 0
-            } else {
-                println!("Condition: Undefined variable {}", var_name);
-                false
+        let range_span = start.span().to(end.span());
+        let start_val = self.evaluate(start, env)?;
+        let end_val = self.evaluate(end, env)?;
+        let (start_val, end_val) = match (start_val, end_val) {
+            (Value::Int(start_val), Value::Int(end_val)) => (start_val, end_val),
+            (start_val, end_val) => {
+                return Err(RuntimeError::new(
+                    RuntimeErrorKind::TypeMismatch,
+                    range_span,
+                    format!("range bounds must be integers, got {} and {}", start_val.type_name(), end_val.type_name()),
+                ));
             }
-        }
+        };
 
-        ASTNode::BinaryOp { left, operator, right } => {
-            let left_val = evaluate_expression(*left, env);
-            let right_val = evaluate_expression(*right, env);
-            println!("Evaluating condition: {} {:?} {}", left_val, operator, right_val);
+        // The iterator lives in a scope of its own around the body, so it
+        // shadows an outer variable of the same name only until the loop ends.
+        env.push_scope();
+        let mut flow = Ok(Flow::Normal);
+        for i in start_val..end_val {
+            self.define(env, var_name.clone(), Value::Int(i));
 
-            match operator {
-                Token::GreaterThan => left_val > right_val,
-                Token::LessThan => left_val < right_val,
-                Token::GreaterEqual => left_val >= right_val,
-                Token::LessEqual => left_val <= right_val,
-                _ => {
-                    println!("Error: Unsupported comparison operator {:?}", operator);
-                    false
+            // Interpret the body of the `for` loop for each iteration.
+            match self.execute(body, env) {
+                Ok(Flow::Normal) => {}
+                Ok(Flow::Continue(target)) if targets_loop(&target, label) => {}
+                Ok(Flow::Break(target)) if targets_loop(&target, label) => break,
+                other => {
+                    flow = other;
+                    break;
                 }
             }
         }
+        env.pop_scope();
+        flow
+    }
+
+    fn execute_switch(&mut self, expression: &ASTNode, cases: &[(ASTNode, ASTNode)], default: Option<&ASTNode>, env: &mut Environment) -> Result<Flow, RuntimeError> {
+        let expr_val = self.evaluate(expression, env)?;
+
+        let mut block = default;
+        for (case_value, case_block) in cases {
+            let case_val = self.evaluate(case_value, env)?;
+
+            if case_val.equals(&expr_val) {
+                block = Some(case_block);
+                break;
+            }
+        }
+
+        let Some(block) = block else {
+            return Ok(Flow::Normal);
+        };
+        // `break` only leaves the `switch`; `continue` and `break outer` go on to
+        // the enclosing loops
+        match self.execute(block, env)? {
+            Flow::Break(None) => Ok(Flow::Normal),
+            flow => Ok(flow),
+        }
+    }
 
-        _ => {
-            println!("Error: Unsupported condition type {:?}", condition);
-            false
+    fn unary_operation(&self, operator: &Token, value: Value, span: Span) -> Result<Value, RuntimeError> {
+        match (operator, value) {
+            // Only `-i64::MIN` overflows
+            (Token::Minus, Value::Int(n)) => match self.overflow {
+                OverflowMode::Checked => n.checked_neg(),
+                OverflowMode::Wrapping => Some(n.wrapping_neg()),
+                OverflowMode::Saturating => Some(n.saturating_neg()),
+            }
+            .map(Value::Int)
+            .ok_or_else(|| {
+                RuntimeError::new(RuntimeErrorKind::IntegerOverflow, span, format!("integer overflow in `-` with {}", n))
+            }),
+            (Token::Minus, Value::Float(n)) => Ok(Value::Float(-n)),
+            (Token::Plus, value @ (Value::Int(_) | Value::Float(_))) => Ok(value),
+            (_, value) => Err(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                span,
+                format!("cannot apply {} to {}", operator.kind(), value.type_name()),
+            )),
         }
     }
+
+    // Applies a binary operator to two evaluated operands.
+    fn binary_operation(&self, operator: &Token, left_val: Value, right_val: Value, span: Span) -> Result<Value, RuntimeError> {
+        // Comparisons look at an int and a float exactly, without promoting
+        let (left_val, right_val) = match operator {
+            Token::EqualEqual | Token::NotEqual
+            | Token::GreaterThan | Token::LessThan | Token::GreaterEqual | Token::LessEqual => (left_val, right_val),
+            _ => promote(left_val, right_val),
+        };
+        let value = match (operator, left_val, right_val) {
+            (Token::Divide, Value::Int(_), Value::Int(0)) => {
+                return Err(RuntimeError::new(RuntimeErrorKind::DivisionByZero, span, "division by zero".to_string()));
+            }
+            (Token::Modulo, Value::Int(_), Value::Int(0)) => {
+                return Err(RuntimeError::new(RuntimeErrorKind::DivisionByZero, span, "modulo by zero".to_string()));
+            }
+            // `%` is the truncated remainder, like Rust's and C's: the result has the
+            // sign of the left side (`-7 % 3` is `-1`, not `2` as with Euclidean
+            // modulo), so that `(a / b) * b + a % b == a` with the truncating `/`
+            (Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::Modulo, Value::Int(l), Value::Int(r)) => {
+                self.integer_arithmetic(operator, l, r, span)?
+            }
+            // Float division by zero follows IEEE 754 and gives `inf` or `NaN`, `%` by zero `NaN`
+            (Token::Plus, Value::Float(l), Value::Float(r)) => Value::Float(l + r),
+            (Token::Minus, Value::Float(l), Value::Float(r)) => Value::Float(l - r),
+            (Token::Multiply, Value::Float(l), Value::Float(r)) => Value::Float(l * r),
+            (Token::Divide, Value::Float(l), Value::Float(r)) => Value::Float(l / r),
+            (Token::Modulo, Value::Float(l), Value::Float(r)) => Value::Float(l % r),
+            (Token::EqualEqual, l, r) => Value::Bool(l.equals(&r)),
+            (Token::NotEqual, l, r) => Value::Bool(!l.equals(&r)),
+            // `+` with a string on either side concatenates
+            (Token::Plus, l @ Value::Str(_), r) | (Token::Plus, l, r @ Value::Str(_)) => {
+                Value::Str(format!("{}{}", l, r).into())
+            }
+            (Token::GreaterThan | Token::LessThan | Token::GreaterEqual | Token::LessEqual, l, r) => {
+                let ordering = match (&l, &r) {
+                    (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+                    (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),  // `None` for NaN
+                    (Value::Int(a), Value::Float(b)) => compare_int_float(*a, *b),
+                    (Value::Float(a), Value::Int(b)) => compare_int_float(*b, *a).map(Ordering::reverse),
+                    (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                    _ => return Err(type_error(span, operator, &l, &r)),
+                };
+                Value::Bool(match operator {
+                    Token::GreaterThan => ordering.is_some_and(|o| o.is_gt()),
+                    Token::LessThan => ordering.is_some_and(|o| o.is_lt()),
+                    Token::GreaterEqual => ordering.is_some_and(|o| o.is_ge()),
+                    _ => ordering.is_some_and(|o| o.is_le()),
+                })
+            }
+            (_, l, r) => return Err(type_error(span, operator, &l, &r)),
+        };
+        Ok(value)
+    }
 }
 
+// Shorthands that run with a default, silent `Interpreter`.
+pub fn interpret_program(program: Program, env: &mut Environment) -> Result<(), RuntimeError> {
+    Interpreter::new().interpret_program(program, env)
+}
+
+pub fn interpret(ast: ASTNode, env: &mut Environment) -> Result<(), RuntimeError> {
+    Interpreter::new().interpret(ast, env)
+}
 
+pub fn evaluate_expression(expr: ASTNode, env: &mut Environment) -> Result<Value, RuntimeError> {
+    Interpreter::new().evaluate_expression(expr, env)
+}
+
+pub fn evaluate_condition(condition: ASTNode, env: &mut Environment) -> Result<bool, RuntimeError> {
+    Interpreter::new().evaluate_condition(condition, env)
+}
@@ -3,8 +3,12 @@
 pub enum Token {
     Var,
     Identifier(String),
-    Number(i64),
+    Number(u64),  // the magnitude; a leading `-` is applied by the parser, so `-9223372036854775808` fits
+    Float(f64),
+    Str(String),
     Equal,
+    EqualEqual,
+    NotEqual,
     Plus,
     Minus,
     Multiply,
@@ -35,150 +39,633 @@ pub enum Token {
     Default,
     Colon,
     Break,
+    Continue,
+    Fn,
+    Return,
+    True,
+    False,
+    And, // `&&` or `and`
+    Or,  // `||` or `or`
+    Not, // `!`
+    NotKeyword, // `not`, like `!` but binding looser than comparisons
     Eof,
 
 }
-pub fn tokenize(input: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();  // Liste der erkannten Tokens
-    let chars: Vec<char> = input.chars().collect();  // Wandelt den Eingabetext in eine Zeichenliste um
-    let mut i = 0;  // Index für die Schleife
 
-    while i < chars.len() {
-        match chars[i] {
-            '=' => {
-                tokens.push(Token::Equal);
+// The kind of a token without its value, used to say which tokens the parser
+// expected (`identifier` instead of one particular name).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    Var,
+    Identifier,
+    Number,
+    Float,
+    Str,
+    Equal,
+    EqualEqual,
+    NotEqual,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    GreaterThan,
+    LessThan,
+    GreaterEqual,
+    LessEqual,
+    Semicolon,
+    Print,
+    If,
+    Else,
+    While,
+    For,
+    Til,
+    In,
+    Range,
+    Switch,
+    Case,
+    Default,
+    Colon,
+    Break,
+    Continue,
+    Fn,
+    Return,
+    True,
+    False,
+    And,
+    Or,
+    Not,
+    NotKeyword,
+    Eof,
+}
 
-            }
-            '+' => {
-                tokens.push(Token::Plus);
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Var => TokenKind::Var,
+            Token::Identifier(_) => TokenKind::Identifier,
+            Token::Number(_) => TokenKind::Number,
+            Token::Float(_) => TokenKind::Float,
+            Token::Str(_) => TokenKind::Str,
+            Token::Equal => TokenKind::Equal,
+            Token::EqualEqual => TokenKind::EqualEqual,
+            Token::NotEqual => TokenKind::NotEqual,
+            Token::Plus => TokenKind::Plus,
+            Token::Minus => TokenKind::Minus,
+            Token::Multiply => TokenKind::Multiply,
+            Token::Divide => TokenKind::Divide,
+            Token::Modulo => TokenKind::Modulo,
+            Token::LeftParen => TokenKind::LeftParen,
+            Token::RightParen => TokenKind::RightParen,
+            Token::LeftBrace => TokenKind::LeftBrace,
+            Token::RightBrace => TokenKind::RightBrace,
+            Token::LeftBracket => TokenKind::LeftBracket,
+            Token::RightBracket => TokenKind::RightBracket,
+            Token::Comma => TokenKind::Comma,
+            Token::GreaterThan => TokenKind::GreaterThan,
+            Token::LessThan => TokenKind::LessThan,
+            Token::GreaterEqual => TokenKind::GreaterEqual,
+            Token::LessEqual => TokenKind::LessEqual,
+            Token::Semicolon => TokenKind::Semicolon,
+            Token::Print => TokenKind::Print,
+            Token::If => TokenKind::If,
+            Token::Else => TokenKind::Else,
+            Token::While => TokenKind::While,
+            Token::For => TokenKind::For,
+            Token::Til => TokenKind::Til,
+            Token::In => TokenKind::In,
+            Token::Range => TokenKind::Range,
+            Token::Switch => TokenKind::Switch,
+            Token::Case => TokenKind::Case,
+            Token::Default => TokenKind::Default,
+            Token::Colon => TokenKind::Colon,
+            Token::Break => TokenKind::Break,
+            Token::Continue => TokenKind::Continue,
+            Token::Fn => TokenKind::Fn,
+            Token::Return => TokenKind::Return,
+            Token::True => TokenKind::True,
+            Token::False => TokenKind::False,
+            Token::And => TokenKind::And,
+            Token::Or => TokenKind::Or,
+            Token::Not => TokenKind::Not,
+            Token::NotKeyword => TokenKind::NotKeyword,
+            Token::Eof => TokenKind::Eof,
+        }
+    }
+}
 
-            }
-            '-' => {
-                tokens.push(Token::Minus);
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = match self {
+            TokenKind::Var => "`var`",
+            TokenKind::Identifier => "identifier",
+            TokenKind::Number => "number",
+            TokenKind::Float => "float",
+            TokenKind::Str => "string",
+            TokenKind::Equal => "`=`",
+            TokenKind::EqualEqual => "`==`",
+            TokenKind::NotEqual => "`!=`",
+            TokenKind::Plus => "`+`",
+            TokenKind::Minus => "`-`",
+            TokenKind::Multiply => "`*`",
+            TokenKind::Divide => "`/`",
+            TokenKind::Modulo => "`%`",
+            TokenKind::LeftParen => "`(`",
+            TokenKind::RightParen => "`)`",
+            TokenKind::LeftBrace => "`{`",
+            TokenKind::RightBrace => "`}`",
+            TokenKind::LeftBracket => "`[`",
+            TokenKind::RightBracket => "`]`",
+            TokenKind::Comma => "`,`",
+            TokenKind::GreaterThan => "`>`",
+            TokenKind::LessThan => "`<`",
+            TokenKind::GreaterEqual => "`>=`",
+            TokenKind::LessEqual => "`<=`",
+            TokenKind::Semicolon => "`;`",
+            TokenKind::Print => "`print`",
+            TokenKind::If => "`if`",
+            TokenKind::Else => "`else`",
+            TokenKind::While => "`while`",
+            TokenKind::For => "`for`",
+            TokenKind::Til => "`til`",
+            TokenKind::In => "`in`",
+            TokenKind::Range => "`..`",
+            TokenKind::Switch => "`switch`",
+            TokenKind::Case => "`case`",
+            TokenKind::Default => "`default`",
+            TokenKind::Colon => "`:`",
+            TokenKind::Break => "`break`",
+            TokenKind::Continue => "`continue`",
+            TokenKind::Fn => "`fn`",
+            TokenKind::Return => "`return`",
+            TokenKind::True => "`true`",
+            TokenKind::False => "`false`",
+            TokenKind::And => "`&&`",
+            TokenKind::Or => "`||`",
+            TokenKind::Not => "`!`",
+            TokenKind::NotKeyword => "`not`",
+            TokenKind::Eof => "end of input",
+        };
+        write!(f, "{}", text)
+    }
+}
 
-            }
-            '*' => {
-                tokens.push(Token::Multiply);
+// How a token is named in error messages, e.g. "`;`" or "identifier `x`".
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Identifier(name) => write!(f, "identifier `{}`", name),
+            Token::Number(value) => write!(f, "number `{}`", value),
+            Token::Float(value) => write!(f, "float `{:?}`", value),
+            Token::Str(text) => write!(f, "string {:?}", text),
+            other => write!(f, "{}", other.kind()),
+        }
+    }
+}
 
-            }
-            '/' => {
-                tokens.push(Token::Divide);
+// Position in the source text: byte offsets `start..end` plus line and column
+// (both 1-based) of the first character.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
 
-            }
-            '%' => {
-                tokens.push(Token::Modulo);
+impl Span {
+    // Span from the start of `self` to the end of `other` (e.g. `if` ... `}`).
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: self.end.max(other.end),
+            ..self
+        }
+    }
+}
 
-            }
-            '(' => {
-                tokens.push(Token::LeftParen);
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
 
+// A token together with the place in the source it was read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexErrorKind {
+    UnknownCharacter,
+    // A single `.`, only `..` is a valid token
+    LoneDot,
+    // Integer literal that does not fit into a u64, or a float that overflows to infinity
+    NumberTooLarge,
+    // String literal without closing `"`
+    UnterminatedString,
+    // Unknown escape sequence after `\` or malformed `\u{..}`
+    InvalidEscape,
+    // `/*` without matching `*/`
+    UnterminatedComment,
+}
+
+// A character (or literal starting with it) that could not be turned into a token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub character: char,
+    pub text: String,  // the source text under `span`, e.g. `\q` for an invalid escape
+    pub span: Span,
+}
+
+impl LexError {
+    // What went wrong, without the location.
+    pub fn message(&self) -> String {
+        match self.kind {
+            LexErrorKind::UnknownCharacter => format!("unknown character `{}`", self.character),
+            LexErrorKind::LoneDot => "unexpected single `.` (did you mean `..`?)".to_string(),
+            LexErrorKind::NumberTooLarge => format!("number literal `{}` is too large", self.text),
+            LexErrorKind::UnterminatedString => "unterminated string literal".to_string(),
+            LexErrorKind::InvalidEscape => format!("invalid escape sequence `{}`", self.text),
+            LexErrorKind::UnterminatedComment => "unterminated block comment".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TriviaKind {
+    LineComment,   // `// ...` bis zum Zeilenende
+    BlockComment,  // `/* ... */`, auch verschachtelt
+}
+
+// Source text the parser does not care about (comments), kept with its
+// position so tools like a formatter can put it back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,  // including the `//` or `/* */` delimiters
+    pub span: Span,
+}
+
+// Splits the input into tokens. Lexing does not stop at the first bad
+// character: all problems in the input are collected and returned together.
+pub fn tokenize(input: &str) -> Result<Vec<SpannedToken>, Vec<LexError>> {
+    tokenize_with_trivia(input).map(|(tokens, _)| tokens)
+}
+
+// Like `tokenize`, but also returns the comments that were skipped.
+pub fn tokenize_with_trivia(input: &str) -> Result<(Vec<SpannedToken>, Vec<Trivia>), Vec<LexError>> {
+    let mut tokens = Vec::new();  // Liste der erkannten Tokens
+    let mut trivia = Vec::new();  // Übersprungene Kommentare
+    let mut errors = Vec::new();  // Liste der Fehler
+    let chars: Vec<(usize, char)> = input.char_indices().collect();  // Zeichen mit ihrem Byte-Offset
+    let mut i = 0;  // Index für die Schleife
+    let mut line = 1;
+    let mut line_start = 0;  // Index des ersten Zeichens der aktuellen Zeile
+
+    // Byte-Offset des Zeichens an Index `i` (oder Ende der Eingabe)
+    let offset = |i: usize| chars.get(i).map(|&(pos, _)| pos).unwrap_or(input.len());
+
+    while i < chars.len() {
+        let start = i;
+        let (start_line, col) = (line, start - line_start + 1);
+        let mut error = None;
+        let mut comment = None;
+        let token = match chars[i].1 {
+            '=' => {
+                if i + 1 < chars.len() && chars[i + 1].1 == '=' {
+                    i += 1;
+                    Some(Token::EqualEqual)
+                } else {
+                    Some(Token::Equal)
+                }
             }
-            ')' => {
-                tokens.push(Token::RightParen);
-            }
-            '{' => {
-                tokens.push(Token::LeftBrace);
-            }
-            '}' => {
-                tokens.push(Token::RightBrace);
+            '+' => Some(Token::Plus),
+            '-' => Some(Token::Minus),
+            '*' => Some(Token::Multiply),
+            '/' => {
+                if i + 1 < chars.len() && chars[i + 1].1 == '/' {
+                    // Zeilenkommentar: bis vor den Zeilenumbruch überspringen
+                    while i + 1 < chars.len() && chars[i + 1].1 != '\n' {
+                        i += 1;
+                    }
+                    comment = Some(TriviaKind::LineComment);
+                    None
+                } else if i + 1 < chars.len() && chars[i + 1].1 == '*' {
+                    // Blockkommentar, `/* /* */ */` ist erlaubt
+                    let mut depth = 0;
+                    while i < chars.len() {
+                        let next = chars.get(i + 1).map(|&(_, c)| c);
+                        match (chars[i].1, next) {
+                            ('/', Some('*')) => {
+                                depth += 1;
+                                i += 1;
+                            }
+                            ('*', Some('/')) => {
+                                depth -= 1;
+                                i += 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            ('\n', _) => {
+                                line += 1;
+                                line_start = i + 1;
+                            }
+                            _ => {}
+                        }
+                        i += 1;
+                    }
+                    if depth == 0 {
+                        comment = Some(TriviaKind::BlockComment);
+                    } else {
+                        error = Some(LexErrorKind::UnterminatedComment);
+                    }
+                    None
+                } else {
+                    Some(Token::Divide)
+                }
             }
-            '[' => tokens.push(Token::LeftBracket),   // Unterstützung für [
-            ']' => tokens.push(Token::RightBracket),  // Unterstützung für ]
-            ',' => tokens.push(Token::Comma),
+            '%' => Some(Token::Modulo),
+            '(' => Some(Token::LeftParen),
+            ')' => Some(Token::RightParen),
+            '{' => Some(Token::LeftBrace),
+            '}' => Some(Token::RightBrace),
+            '[' => Some(Token::LeftBracket),   // Unterstützung für [
+            ']' => Some(Token::RightBracket),  // Unterstützung für ]
+            ',' => Some(Token::Comma),
             '>' => {
-                if i + 1 < chars.len() && chars[i + 1] == '=' {
-                    tokens.push(Token::GreaterEqual);
+                if i + 1 < chars.len() && chars[i + 1].1 == '=' {
                     i += 1;
+                    Some(Token::GreaterEqual)
                 } else {
-                    tokens.push(Token::GreaterThan);
+                    Some(Token::GreaterThan)
                 }
             }
             '<' => {
-                if i + 1 < chars.len() && chars[i + 1] == '=' {
-                    tokens.push(Token::LessEqual);
+                if i + 1 < chars.len() && chars[i + 1].1 == '=' {
                     i += 1;
+                    Some(Token::LessEqual)
                 } else {
-                    tokens.push(Token::LessThan);
+                    Some(Token::LessThan)
                 }
             }
-            ';' => {
-                tokens.push(Token::Semicolon);
+            ';' => Some(Token::Semicolon),
+            '!' => {
+                if i + 1 < chars.len() && chars[i + 1].1 == '=' {
+                    i += 1;
+                    Some(Token::NotEqual)
+                } else {
+                    Some(Token::Not)
+                }
+            }
+            '&' | '|' => {
+                // Nur doppelt gültig: `&&` und `||`
+                let c = chars[i].1;
+                if i + 1 < chars.len() && chars[i + 1].1 == c {
+                    i += 1;
+                    Some(if c == '&' { Token::And } else { Token::Or })
+                } else {
+                    error = Some(LexErrorKind::UnknownCharacter);
+                    None
+                }
             }
             '.' => {
                 // Check for two consecutive dots `..`
-                if i + 1 < chars.len() && chars[i + 1] == '.' {
-                    tokens.push(Token::Range);
+                if i + 1 < chars.len() && chars[i + 1].1 == '.' {
                     i += 1;  // Skip the second dot
+                    Some(Token::Range)
                 } else {
-                    println!("Unbekanntes Zeichen: .");
+                    error = Some(LexErrorKind::LoneDot);
+                    None
                 }
             }
-            ':' =>{tokens.push(Token::Colon);
-
+            ':' => Some(Token::Colon),
+            '"' => {
+                // String-Literal bis zum schließenden `"` einlesen
+                let mut text = String::new();
+                let mut closed = false;
+                i += 1;
+                while i < chars.len() {
+                    match chars[i].1 {
+                        '"' => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' => {
+                            let escape_start = i;
+                            i += 1;
+                            let escaped = match chars.get(i).map(|&(_, c)| c) {
+                                Some('n') => Some('\n'),
+                                Some('t') => Some('\t'),
+                                Some('"') => Some('"'),
+                                Some('\\') => Some('\\'),
+                                Some('u') => {
+                                    // Unicode-Escape der Form \u{1F600}
+                                    let mut hex = String::new();
+                                    let mut valid = chars.get(i + 1).map(|&(_, c)| c) == Some('{');
+                                    if valid {
+                                        i += 2;
+                                        while i < chars.len() && chars[i].1 != '}' && chars[i].1 != '"' {
+                                            hex.push(chars[i].1);
+                                            i += 1;
+                                        }
+                                        valid = i < chars.len() && chars[i].1 == '}';
+                                        if !valid {
+                                            i -= 1;  // `"` oder Eingabeende nicht verschlucken
+                                        }
+                                    }
+                                    if valid && (1..=6).contains(&hex.len()) {
+                                        u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                                    } else {
+                                        None
+                                    }
+                                }
+                                _ => None,
+                            };
+                            match escaped {
+                                Some(c) => text.push(c),
+                                None => {
+                                    let span = Span {
+                                        start: offset(escape_start),
+                                        end: offset(i + 1),
+                                        line,
+                                        col: escape_start - line_start + 1,
+                                    };
+                                    errors.push(LexError {
+                                        kind: LexErrorKind::InvalidEscape,
+                                        character: '\\',
+                                        text: input[span.start..span.end].to_string(),
+                                        span,
+                                    });
+                                }
+                            }
+                        }
+                        '\n' => {
+                            line += 1;
+                            line_start = i + 1;
+                            text.push('\n');
+                        }
+                        c => text.push(c),
+                    }
+                    i += 1;
+                }
+                if closed {
+                    Some(Token::Str(text))
+                } else {
+                    error = Some(LexErrorKind::UnterminatedString);
+                    None
+                }
             }
 
-            ' ' | '\n' | '\t' => {
-                // Ignoriere Leerzeichen und Zeilenumbrüche
+            '\n' => {
+                // Zeilenumbruch: Zeilennummer für die Spans mitzählen
+                line += 1;
+                line_start = i + 1;
+                None
+            }
+            ' ' | '\t' | '\r' => {
+                // Ignoriere Leerzeichen
+                None
             }
-            c if c.is_digit(10) => {
+            c if c.is_ascii_digit() => {
+                let digit_at = |i: usize| chars.get(i).is_some_and(|&(_, c)| c.is_ascii_digit());
                 let mut num = String::new();
-                while i < chars.len() && chars[i].is_digit(10) {
-                    num.push(chars[i]);
+                let mut is_float = false;
+                while digit_at(i) {
+                    num.push(chars[i].1);
                     i += 1;
                 }
+                // Nachkommastellen nur, wenn nach dem `.` eine Ziffer folgt (`0..10` bleibt ein Range)
+                if i < chars.len() && chars[i].1 == '.' && digit_at(i + 1) {
+                    is_float = true;
+                    num.push('.');
+                    i += 1;
+                    while digit_at(i) {
+                        num.push(chars[i].1);
+                        i += 1;
+                    }
+                }
+                // Exponent wie `2e-3` oder `1E10`
+                if i < chars.len() && (chars[i].1 == 'e' || chars[i].1 == 'E') {
+                    let sign = chars.get(i + 1).is_some_and(|&(_, c)| c == '+' || c == '-');
+                    let digits_start = if sign { i + 2 } else { i + 1 };
+                    if digit_at(digits_start) {
+                        is_float = true;
+                        num.push('e');
+                        if sign {
+                            num.push(chars[i + 1].1);
+                        }
+                        i = digits_start;
+                        while digit_at(i) {
+                            num.push(chars[i].1);
+                            i += 1;
+                        }
+                    }
+                }
                 i -= 1;
-                let number = num.parse::<i64>().unwrap();
-                tokens.push(Token::Number(number));
+                if is_float {
+                    match num.parse::<f64>() {
+                        Ok(number) if number.is_finite() => Some(Token::Float(number)),
+                        _ => {
+                            error = Some(LexErrorKind::NumberTooLarge);
+                            None
+                        }
+                    }
+                } else {
+                    match num.parse::<u64>() {
+                        Ok(number) => Some(Token::Number(number)),
+                        Err(_) => {
+                            error = Some(LexErrorKind::NumberTooLarge);
+                            None
+                        }
+                    }
+                }
             }
-            c if c.is_alphabetic() => {
+            // Bezeichner: Buchstabe oder `_`, danach auch Ziffern. Wie bei Zahlen
+            // zählen nur ASCII-Ziffern, `x٣` oder `a²` sind keine Bezeichner
+            c if c.is_alphabetic() || c == '_' => {
                 let mut ident = String::new();
-                while i < chars.len() && chars[i].is_alphabetic() {
-                    ident.push(chars[i]);
+                while i < chars.len() && (chars[i].1.is_alphabetic() || chars[i].1.is_ascii_digit() || chars[i].1 == '_') {
+                    ident.push(chars[i].1);
                     i += 1;
                 }
                 i -= 1;
                 match ident.as_str() {
-                    "var" => {
-                        tokens.push(Token::Var);
-                    }
-                    "print" => {
-                        tokens.push(Token::Print);
-                    }
-                    "if" => {
-                        tokens.push(Token::If);
-                    }
-                    "else" => {
-                        tokens.push(Token::Else);
-                    }
-                    "while" => {
-                        tokens.push(Token::While);
-                    }
-                    "for" =>{
-                        tokens.push(Token::For);
-                    }
-                    ".." => {
-                        tokens.push(Token::Til);
-                    }
-                    "switch" => tokens.push(Token::Switch),
-                    "case" => tokens.push(Token::Case),
-                    "default" => tokens.push(Token::Default),
-                    "break" => tokens.push(Token::Break),
-                    "in"=>{
-                        tokens.push(Token::In);
-                    }
-                    _ => {
-                        tokens.push(Token::Identifier(ident.clone()));
-                    }
+                    "var" => Some(Token::Var),
+                    "print" => Some(Token::Print),
+                    "if" => Some(Token::If),
+                    "else" => Some(Token::Else),
+                    "while" => Some(Token::While),
+                    "for" => Some(Token::For),
+                    ".." => Some(Token::Til),
+                    "switch" => Some(Token::Switch),
+                    "case" => Some(Token::Case),
+                    "default" => Some(Token::Default),
+                    "break" => Some(Token::Break),
+                    "continue" => Some(Token::Continue),
+                    "fn" => Some(Token::Fn),
+                    "return" => Some(Token::Return),
+                    "in" => Some(Token::In),
+                    "true" => Some(Token::True),
+                    "false" => Some(Token::False),
+                    "and" => Some(Token::And),
+                    "or" => Some(Token::Or),
+                    "not" => Some(Token::NotKeyword),
+                    _ => Some(Token::Identifier(ident.clone())),
                 }
             }
-            _ => println!("Unbekanntes Zeichen: {}", chars[i]),
-        }
+            _ => {
+                error = Some(LexErrorKind::UnknownCharacter);
+                None
+            }
+        };
         i += 1;
-    }
-
-    tokens.push(Token::Eof);
-    tokens
-}
 
+        let span = Span {
+            start: offset(start),
+            end: offset(i),
+            line: start_line,
+            col,
+        };
+        if let Some(token) = token {
+            tokens.push(SpannedToken { token, span });
+        }
+        if let Some(kind) = comment {
+            trivia.push(Trivia { kind, text: input[span.start..span.end].to_string(), span });
+        }
+        if let Some(kind) = error {
+            errors.push(LexError { kind, character: chars[start].1, text: input[span.start..span.end].to_string(), span });
+        }
+    }
 
+    if !errors.is_empty() {
+        return Err(errors);
+    }
 
+    tokens.push(SpannedToken {
+        token: Token::Eof,
+        span: Span {
+            start: input.len(),
+            end: input.len(),
+            line,
+            col: chars.len() - line_start + 1,
+        },
+    });
+    Ok((tokens, trivia))
+}
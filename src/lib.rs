@@ -1,3 +1,7 @@
 pub mod lexer;
 pub mod parser;
 pub mod interpreter;
+pub mod environment;
+pub mod diagnostic;
+pub mod trace;
+pub mod stack;
@@ -1,49 +1,110 @@
-mod lexer;
-mod parser;
-mod interpreter;
+use std::io::Write;
+use Xene::lexer::tokenize;
+use Xene::parser::{parse_tokens, ParseErrors, TokenStream};  // Importiere den Parser
+use Xene::interpreter::Interpreter;
+use Xene::environment::Environment;
+use Xene::trace::StderrTracer;
 
-use std::collections::HashMap;
-use std::io::{self, Write};
-use parser::{ASTNode, parse_assignment, parse_expression,parse_if,parse_block};  // Importiere die Parser-Funktionen
-use lexer::tokenize;
-use interpreter::interpret;
-use crate::parser::parse_while;
+// Wie tief Funktionsaufrufe verschachtelt sein dürfen
+const MAX_CALL_DEPTH: usize = 10_000;
+
+// Stack des Interpreter-Threads. Parser und Interpreter dürfen fast alles
+// davon nutzen, der Rest bleibt für `run` und `start` übrig
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+const STACK_LIMIT: usize = STACK_SIZE - 1024 * 1024;
+
+// Parst und führt `source` aus. Mit `trace` werden Parser- und Auswertungsschritte
+// nach stderr geschrieben. Gibt `false` zurück, wenn Parsen oder Ausführung fehlschlägt.
+fn run(source: &str, env: &mut Environment, trace: bool) -> bool {
+    let mut tracer = StderrTracer;
+    let parsed = tokenize(source).map_err(ParseErrors::Lex).and_then(|tokens| {
+        let mut stream = TokenStream::new(&tokens).recovering().with_stack_limit(STACK_LIMIT);
+        if trace {
+            stream = stream.with_tracer(&mut tracer);
+        }
+        parse_tokens(stream)
+    });
+
+    match parsed {
+        Ok(program) => {
+            let mut interpreter = Interpreter::new().with_max_call_depth(MAX_CALL_DEPTH).with_stack_limit(STACK_LIMIT);
+            if trace {
+                interpreter = interpreter.with_tracer(&mut tracer);
+            }
+            match interpreter.interpret_program(program, env) {
+                Ok(()) => true,
+                Err(error) => {
+                    eprint!("{}", error.render(source));
+                    false
+                }
+            }
+        }
+        Err(errors) => {
+            eprint!("{}", errors.render(source));  // Alle Fehler auf einmal anzeigen
+            false
+        }
+    }
+}
 
 fn main() {
-    println!("Willkommen bei Xene!");
+    // Jede Verschachtelungsebene braucht Stack; der Haupt-Thread hätte für
+    // `MAX_CALL_DEPTH` Aufrufe zu wenig, also läuft alles in einem eigenen Thread
+    let interpreter_thread = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(start)
+        .expect("Fehler beim Starten des Interpreter-Threads");
+    if interpreter_thread.join().is_err() {
+        std::process::exit(101);  // Die Panik wurde schon ausgegeben
+    }
+}
 
-    let mut env = HashMap::new();  // Die Umgebung für Variablen
+fn start() {
+    let mut env = Environment::new();  // Die Umgebung für Variablen
+
+    // `--trace` schaltet die Ablaufverfolgung ein, standardmäßig bleibt alles still
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let trace = args.iter().any(|arg| arg == "--trace");
+    args.retain(|arg| arg != "--trace");
+
+    // Mit einem Dateipfad wird die ganze Datei ausgeführt, sonst startet die REPL
+    if let Some(path) = args.first() {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Fehler beim Lesen von {}: {}", path, error);
+                std::process::exit(1);
+            }
+        };
+        if !run(&source, &mut env, trace) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    println!("Willkommen bei Xene!");
 
     loop {
         print!("xene> ");
         std::io::stdout().flush().unwrap();
 
         let mut input = String::new();
-        std::io::stdin().read_line(&mut input).expect("Fehler beim Lesen der Eingabe");
+        // Ende der Eingabe (Strg+D oder eine umgeleitete Datei) beendet die REPL wie `exit`
+        let at_end = match std::io::stdin().read_line(&mut input) {
+            Ok(read) => read == 0,
+            Err(error) => {
+                eprintln!("Fehler beim Lesen der Eingabe: {}", error);
+                std::process::exit(1);
+            }
+        };
 
         let trimmed = input.trim();
 
-        if trimmed == "exit" {
+        if at_end || trimmed == "exit" {
             println!("Goodbye!");
             break;
         }
 
-        let mut tokens = tokenize(trimmed);
-
-        if let Some(ast) = parse_if(&mut tokens) {
-            interpret(ast, &mut env);  // Interpretiere die If-Anweisung
-        }
-        else if let Some(ast)= parse_while(&mut tokens) {
-            interpret(ast, &mut env);
-        }
-        else if let Some(ast) = parse_assignment(&mut tokens) {
-            interpret(ast, &mut env);  // Interpretiere den AST und führe die Zuweisung aus
-        }
-        // Versuche eine Expression zu parsen
-        else if let Some(ast) = parse_expression(&mut tokens) {
-            interpret(ast, &mut env);  // Interpretiere die Expression (arithmetische Operation)
-        } else {
-            println!("Invalid Expression!");
-        }
+        // Eine Zeile ist ein kleines Programm: dieselbe Grammatik wie für Dateien
+        run(trimmed, &mut env, trace);
     }
 }
@@ -1,571 +1,1175 @@
-use crate::lexer::Token;  // Import tokens from lexer
-
+use std::rc::Rc;
+use crate::diagnostic;
+use crate::lexer::{tokenize, LexError, Span, SpannedToken, Token, TokenKind};  // Import tokens from lexer
+use crate::stack::{StackGuard, DEFAULT_STACK_LIMIT};
+use crate::trace::{TraceEvent, Tracer};
+
+// Every node carries the span of the source text it was parsed from, so
+// diagnostics can point at the exact location.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ASTNode {
-    Assignment { var_name: String, value: Box<ASTNode> },
-    Number(i64),
-    Identifier(String),
-    BinaryOp { left: Box<ASTNode>, operator: Token, right: Box<ASTNode> },
-    Block(Vec<ASTNode>),
+    VarDecl { var_name: String, value: Box<ASTNode>, span: Span },  // `var x = 1`
+    Assign { var_name: String, value: Box<ASTNode>, span: Span },  // `x = 1`, `x` must already exist
+    Number(i64, Span),
+    Float(f64, Span),
+    Bool(bool, Span),
+    Str(String, Span),
+    Identifier(String, Span),
+    BinaryOp { left: Box<ASTNode>, operator: Token, right: Box<ASTNode>, span: Span },
+    UnaryOp { operator: Token, operand: Box<ASTNode>, span: Span },
+    Block(Vec<ASTNode>, Span),
     If {
         condition: Box<ASTNode>,
         then_branch: Box<ASTNode>,
         else_branch: Option<Box<ASTNode>>,
+        span: Span,
     },
     While {
         condition: Box<ASTNode>,
         body: Box<ASTNode>,
+        label: Option<String>,  // `outer: while ...`
+        span: Span,
     },
     For {
         iterator: Box<ASTNode>,
         iterable: Box<ASTNode>,
         body: Box<ASTNode>,
+        label: Option<String>,
+        span: Span,
     },
     Range{
         start: Box<ASTNode>,
         end: Box<ASTNode>,
+        span: Span,
     },
     Switch {
         expression: Box<ASTNode>,
         cases: Vec<(ASTNode, ASTNode)>, // Each case has a value and a block
         default: Option<Box<ASTNode>>,  // Optional default block
+        span: Span,
+    },
+    List(Vec<ASTNode>, Span),
+    Print(Box<ASTNode>, Span),
+    Function {
+        name: String,
+        params: Vec<String>,
+        body: Rc<ASTNode>,  // shared with the function values made from it
+        span: Span,
     },
-    List(Vec<ASTNode>),
-    Print(Box<ASTNode>),
+    Call { callee: Box<ASTNode>, args: Vec<ASTNode>, span: Span },
+    Return(Option<Box<ASTNode>>, Span),  // `return;` has no value
+    Break(Option<String>, Span),  // `break;` or `break outer;`
+    Continue(Option<String>, Span),
 
 }
 
-pub fn parse_assignment(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    println!("____Starting to parse assignment, current token: {:?},{:?}", tokens.get(0),tokens.get(1));
+impl ASTNode {
+    pub fn span(&self) -> Span {
+        match self {
+            ASTNode::VarDecl { span, .. }
+            | ASTNode::Assign { span, .. }
+            | ASTNode::BinaryOp { span, .. }
+            | ASTNode::UnaryOp { span, .. }
+            | ASTNode::If { span, .. }
+            | ASTNode::While { span, .. }
+            | ASTNode::For { span, .. }
+            | ASTNode::Range { span, .. }
+            | ASTNode::Switch { span, .. }
+            | ASTNode::Function { span, .. }
+            | ASTNode::Call { span, .. } => *span,
+            ASTNode::Number(_, span)
+            | ASTNode::Float(_, span)
+            | ASTNode::Bool(_, span)
+            | ASTNode::Str(_, span)
+            | ASTNode::Identifier(_, span)
+            | ASTNode::Block(_, span)
+            | ASTNode::List(_, span)
+            | ASTNode::Print(_, span)
+            | ASTNode::Return(_, span)
+            | ASTNode::Break(_, span)
+            | ASTNode::Continue(_, span) => *span,
+        }
+    }
 
-    if let Some(Token::Var) = tokens.get(0).cloned() {
-        tokens.remove(0);  // Remove `var`
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            ASTNode::VarDecl { span, .. }
+            | ASTNode::Assign { span, .. }
+            | ASTNode::BinaryOp { span, .. }
+            | ASTNode::UnaryOp { span, .. }
+            | ASTNode::If { span, .. }
+            | ASTNode::While { span, .. }
+            | ASTNode::For { span, .. }
+            | ASTNode::Range { span, .. }
+            | ASTNode::Switch { span, .. }
+            | ASTNode::Function { span, .. }
+            | ASTNode::Call { span, .. } => span,
+            ASTNode::Number(_, span)
+            | ASTNode::Float(_, span)
+            | ASTNode::Bool(_, span)
+            | ASTNode::Str(_, span)
+            | ASTNode::Identifier(_, span)
+            | ASTNode::Block(_, span)
+            | ASTNode::List(_, span)
+            | ASTNode::Print(_, span)
+            | ASTNode::Return(_, span)
+            | ASTNode::Break(_, span)
+            | ASTNode::Continue(_, span) => span,
+        }
+    }
+}
 
-        if let Some(Token::Identifier(var_name)) = tokens.get(0).cloned() {
-            tokens.remove(0);  // Remove the variable name
+// Cursor over the tokens produced by the lexer. Parsing only moves the
+// position forward, and `checkpoint`/`rewind` let a caller try one rule and
+// go back to where it started if that rule does not match. Syntax errors the
+// parser recovered from (see `recovering`) are collected here as well.
+#[derive(Debug)]
+pub struct TokenStream<'a> {
+    tokens: &'a [SpannedToken],
+    pos: usize,
+    errors: Vec<ParseError>,
+    tracer: Option<&'a mut dyn Tracer>,
+    function_depth: usize,  // how many `fn` bodies we are in, `return` needs at least one
+    loop_depth: usize,  // loops around the current statement (inside the current `fn`), for `continue`
+    labels: Vec<String>,  // labels of those loops, innermost last
+    switch_depth: usize,  // same for `switch`, `break` needs a loop or a `switch`
+    nesting: usize,  // statements and expressions we are in, see `nested`
+    stack: StackGuard,
+    recover: bool,
+}
 
-            if let Some(Token::Equal) = tokens.get(0).cloned() {
-                tokens.remove(0);  // Remove the equal sign `=`
+impl<'a> TokenStream<'a> {
+    pub fn new(tokens: &'a [SpannedToken]) -> Self {
+        TokenStream { tokens, pos: 0, errors: Vec::new(), tracer: None, function_depth: 0, loop_depth: 0, labels: Vec::new(), switch_depth: 0, nesting: 0, stack: StackGuard::new(DEFAULT_STACK_LIMIT), recover: false }
+    }
 
-                // Now parse an expression (e.g., `5 + 3`)
-                if let Some(expression) = parse_expression(tokens) {
-                    // Check for semicolon after the assignment
-                    if let Some(Token::Semicolon) = tokens.get(0).cloned() {
-                        tokens.remove(0);  // Remove the semicolon `;`
-                        println!("Semicolon removed after assignment.");
-                    } else {
-                        println!("Optional: No semicolon found after assignment.");
-                    }
+    // Records a broken statement in a block or `switch` in `errors` and goes
+    // on with the next one, leaving it out of the result. Without this the
+    // first syntax error is returned as `Err`. `parse_program` always recovers.
+    pub fn recovering(mut self) -> Self {
+        self.recover = true;
+        self
+    }
 
+    // How much native stack the parser may use before it stops with a
+    // `NestingTooDeep` error; `DEFAULT_STACK_LIMIT` by default. Above the
+    // default, parse on a thread with more stack than that.
+    pub fn with_stack_limit(mut self, limit: usize) -> Self {
+        self.stack.set_limit(limit);
+        self
+    }
 
-                    println!("______FInished Parsing");
-                    // Return the assignment AST node
-                    return Some(ASTNode::Assignment {
-                        var_name: var_name.clone(),
-                        value: Box::new(expression),
-                    });
-                } else {
-                    println!("Error: Invalid expression in assignment.");
-                    return None;
-                }
-            }
+    // Reports every grammar rule the parser enters to `tracer`.
+    pub fn with_tracer(mut self, tracer: &'a mut dyn Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    fn trace_rule(&mut self, rule: &'static str) {
+        let span = self.current_span();
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&TraceEvent::Parse { rule, span });
         }
     }
 
-    println!("Failed to parse assignment.");
-    None
-}
+    // The current token, if any.
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.peek_nth(0)
+    }
 
-pub fn parse_expression(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    println!("Starting parse_expression, current token: {:?}", tokens.get(0));
+    // The token `n` positions after the current one.
+    pub fn peek_nth(&self, n: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + n).map(|t| &t.token)
+    }
+
+    // Span of the current token, used to locate error messages.
+    pub fn current_span(&self) -> Span {
+        self.tokens.get(self.pos).or(self.tokens.last()).map(|t| t.span).unwrap_or_default()
+    }
 
-    // Case 0: Parse a range expression (e.g., `1..10`)
-    if let Some(Token::Number(start)) = tokens.get(0).cloned() {
-        tokens.remove(0); // Remove the start number
+    // Span of the token before the current one, i.e. the last one consumed.
+    pub fn previous_span(&self) -> Span {
+        self.pos.checked_sub(1).and_then(|pos| self.tokens.get(pos)).map(|t| t.span).unwrap_or_default()
+    }
 
-        // Check if the next token is `..`
-        if let Some(Token::Range) = tokens.get(0).cloned() {
-            tokens.remove(0); // Remove the range operator `..`
+    // Consumes the current token and returns its span.
+    pub fn advance(&mut self) -> Span {
+        let span = self.current_span();
+        if self.pos < self.tokens.len() {
+            self.pos += 1;
+        }
+        span
+    }
 
-            // Check if there's a number for the end value of the range
-            if let Some(Token::Number(end)) = tokens.get(0).cloned() {
-                tokens.remove(0); // Remove the end number
+    // True once only `Eof` (or nothing) is left.
+    pub fn is_at_end(&self) -> bool {
+        matches!(self.peek(), None | Some(Token::Eof))
+    }
 
-                println!("___detected range: {}..{}", start, end);
-                return Some(ASTNode::Range {
-                    start: Box::new(ASTNode::Number(start)),
-                    end: Box::new(ASTNode::Number(end)),
-                });
-            } else {
-                println!("Error: Expected a number after `..` for the range end.");
-                return None;
-            }
+    pub fn checkpoint(&self) -> usize {
+        self.pos
+    }
+
+    pub fn rewind(&mut self, checkpoint: usize) {
+        self.pos = checkpoint;
+    }
+
+    // Whether the tokens consumed since `checkpoint` open more `{` than they close.
+    pub fn leaves_brace_open(&self, checkpoint: usize) -> bool {
+        let depth: isize = self.tokens[checkpoint..self.pos]
+            .iter()
+            .map(|t| match t.token {
+                Token::LeftBrace => 1,
+                Token::RightBrace => -1,
+                _ => 0,
+            })
+            .sum();
+        depth > 0
+    }
+
+    // Runs `parse` and rewinds to where it started if it fails, so the next
+    // alternative sees the input untouched.
+    pub fn attempt<T, E>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, E>) -> Result<T, E> {
+        let checkpoint = self.checkpoint();
+        let error_count = self.errors.len();
+        let result = parse(self);
+        if result.is_err() {
+            self.rewind(checkpoint);
+            self.errors.truncate(error_count);
+        }
+        result
+    }
+
+    // Runs `parse` one nesting level deeper. Every statement and expression
+    // goes through here, so however the input is nested (`((((1))))`,
+    // `- - - 1`, `{{{ }}}`, `[[[ ]]]`) the parser fails with `NestingTooDeep`
+    // once it used up its stack limit, instead of overflowing the stack.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.nesting == 0 {
+            self.stack.start();
+        } else if self.stack.exhausted() {
+            return Err(self.error(ParseErrorKind::NestingTooDeep, &[]));
         }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
 
-        // If no range, treat it as a primary number expression and continue parsing.
-        return parse_binary_op_with_left(tokens, ASTNode::Number(start));
+    // Records an error the parser recovered from.
+    pub fn report(&mut self, error: ParseError) {
+        self.errors.push(error);
     }
 
-    // Case 1: Parse an assignment expression (e.g., `x = x - 1`)
-    if let Some(Token::Identifier(var_name)) = tokens.get(0).cloned() {
-        tokens.remove(0); // Remove the variable name
+    // The errors recovered from so far, in source order.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
 
-        // Check if the next token is an equal sign (`=`)
-        if let Some(Token::Equal) = tokens.get(0).cloned() {
-            tokens.remove(0); // Remove the equal sign `=`
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
 
-            if let Some(right_expr) = parse_expression(tokens) {
-                println!("Parsed assignment: {} = {:?}", var_name, right_expr);
-                return Some(ASTNode::Assignment {
-                    var_name,
-                    value: Box::new(right_expr),
-                });
-            } else {
-                println!("Error: Expected an expression after `=`");
-                return None;
+    // Panic-mode recovery: skips the rest of a broken statement up to and
+    // including its `;`, or up to the `}` that closes the enclosing block.
+    // Braces opened while skipping are skipped as a whole, and a statement
+    // broken in its header (`if 1 > { ... }`) ends with its body, so the
+    // statement after it is still checked. An `else` after that body still
+    // belongs to the broken statement.
+    pub fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                None | Some(Token::Eof) => break,
+                Some(Token::Semicolon) if depth == 0 => {
+                    self.advance();
+                    break;
+                }
+                Some(Token::RightBrace) if depth == 0 => break,
+                Some(Token::LeftBrace) => depth += 1,
+                Some(Token::RightBrace) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        if self.peek() != Some(&Token::Else) {
+                            break;
+                        }
+                    }
+                }
+                Some(_) => {}
             }
+            self.advance();
         }
+    }
 
-        // Parse binary expressions or return the identifier itself
-        return parse_binary_expression_or_variable(tokens, var_name);
+    // An error of `kind` at the current token.
+    pub fn error(&self, kind: ParseErrorKind, expected: &[TokenKind]) -> ParseError {
+        ParseError {
+            kind,
+            expected: expected.to_vec(),
+            found: self.peek().cloned().unwrap_or(Token::Eof),
+            span: self.current_span(),
+        }
     }
 
-    // Handle `print` statements
-    if let Some(Token::Print) = tokens.get(0).cloned() {
-        return parse_print(tokens);
+    // The current token is none of `expected`.
+    pub fn unexpected(&self, expected: &[TokenKind]) -> ParseError {
+        self.error(ParseErrorKind::UnexpectedToken, expected)
     }
 
-    // Parse primary expressions and potential binary operations
-    if let Some(left) = parse_primary_expression(tokens) {
-        return parse_binary_op_with_left(tokens, left);
+    // Consumes the current token if it is of kind `expected`, returning its span.
+    pub fn expect(&mut self, expected: TokenKind) -> Result<Span, ParseError> {
+        if self.peek().map(Token::kind) == Some(expected) {
+            Ok(self.advance())
+        } else {
+            Err(self.unexpected(&[expected]))
+        }
     }
 
-    println!("No valid expression found, returning `None`");
-    None
+    // Like `expect` for the closing `)`, `}` or `]`. Running out of input
+    // instead is reported at the opening delimiter at `open`.
+    fn expect_closing(&mut self, closing: TokenKind, open: Span) -> Result<Span, ParseError> {
+        if self.is_at_end() {
+            return Err(ParseError { span: open, ..self.error(ParseErrorKind::UnclosedDelimiter, &[closing]) });
+        }
+        self.expect(closing)
+    }
 }
 
+// A whole source file: the statements in the order they appear.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub statements: Vec<ASTNode>,
+}
 
-pub fn parse_print(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    tokens.remove(0);  // Remove `print`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    // `found` is none of the `expected` tokens
+    UnexpectedToken,
+    // No expression can start with `found`
+    ExpectedExpression,
+    // `(`, `{` or `[` still open at the end of the input; the span points at the opening one
+    UnclosedDelimiter,
+    // An integer literal outside of i64 (only `-9223372036854775808` may go past `i64::MAX`)
+    NumberTooLarge,
+    // `return` at the top level instead of inside a function body
+    ReturnOutsideFunction,
+    // `break` outside of a loop or `switch`
+    BreakOutsideLoop,
+    // `continue` outside of a loop
+    ContinueOutsideLoop,
+    // `break name` or `continue name` without an enclosing loop labeled `name`
+    UnknownLabel,
+    // A function with two parameters of the same name, e.g. `fn f(a, a)`
+    DuplicateParameter,
+    // Statements or expressions nested deeper than `TokenStream::with_stack_limit` allows
+    NestingTooDeep,
+}
 
-    // Expect an opening parenthesis `(` after `print`
-    if let Some(Token::LeftParen) = tokens.get(0).cloned() {
-        tokens.remove(0);  // Remove `(`
-
-        // Expect the expression inside the parentheses (e.g., the variable to print)
-        if let Some(expression) = parse_primary_expression(tokens) {
-            // Expect the closing parenthesis `)`
-            if let Some(Token::RightParen) = tokens.get(0).cloned() {
-                tokens.remove(0);  // Remove `)`
-                // Now expect a semicolon `;` to end the statement
-                if let Some(Token::Semicolon) = tokens.get(0).cloned() {
-                    tokens.remove(0);  // Remove `;`
-                    return Some(ASTNode::Print(Box::new(expression)));
-                } else {
-                    println!("Error: Missing semicolon after `print` statement");
-                    return None;
-                }
-            } else {
-                println!("Error: Missing closing parenthesis `)` after expression");
-                return None;
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub expected: Vec<TokenKind>,
+    pub found: Token,
+    pub span: Span,
+}
+
+impl ParseError {
+    // The error with the offending source line and a caret under it.
+    pub fn render(&self, source: &str) -> String {
+        diagnostic::render(source, self.span, &self.to_string())
+    }
+}
+
+// "`;`", "`,` or `]`", "one of `case`, `default`, `}`"
+fn describe_expected(expected: &[TokenKind]) -> String {
+    let names: Vec<String> = expected.iter().map(|kind| kind.to_string()).collect();
+    match names.as_slice() {
+        [] => "something else".to_string(),
+        [name] => name.clone(),
+        [first, second] => format!("{} or {}", first, second),
+        _ => format!("one of {}", names.join(", ")),
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            ParseErrorKind::UnexpectedToken => {
+                write!(f, "expected {}, found {}", describe_expected(&self.expected), self.found)
             }
-        } else {
-            println!("Error: Invalid expression inside `print`");
-            return None;
+            ParseErrorKind::ExpectedExpression => write!(f, "expected an expression, found {}", self.found),
+            ParseErrorKind::UnclosedDelimiter => {
+                write!(f, "unclosed delimiter, expected {} before {}", describe_expected(&self.expected), self.found)
+            }
+            ParseErrorKind::NumberTooLarge => write!(f, "integer literal too large, found {}", self.found),
+            ParseErrorKind::ReturnOutsideFunction => write!(f, "`return` outside of a function"),
+            ParseErrorKind::BreakOutsideLoop => write!(f, "`break` outside of a loop or `switch`"),
+            ParseErrorKind::ContinueOutsideLoop => write!(f, "`continue` outside of a loop"),
+            ParseErrorKind::UnknownLabel => match &self.found {
+                Token::Identifier(label) => write!(f, "no enclosing loop is labeled `{}`", label),
+                found => write!(f, "unknown loop label, found {}", found),
+            },
+            ParseErrorKind::DuplicateParameter => match &self.found {
+                Token::Identifier(param) => write!(f, "duplicate parameter `{}`", param),
+                found => write!(f, "duplicate parameter, found {}", found),
+            },
+            ParseErrorKind::NestingTooDeep => write!(f, "nested too deeply"),
         }
-    } else {
-        println!("Error: Missing opening parenthesis `(` after `print`");
-        return None;
     }
 }
 
-pub fn parse_if(tokens: &mut Vec<Token>) -> Option<ASTNode> {
+// Everything that kept `parse_program` from producing a `Program`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrors {
+    Lex(Vec<LexError>),
+    Syntax(Vec<ParseError>),
+}
 
+impl ParseErrors {
+    // All errors as rustc-style snippets of `source`.
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            ParseErrors::Lex(errors) => errors
+                .iter()
+                .map(|error| diagnostic::render(source, error.span, &error.message()))
+                .collect(),
+            ParseErrors::Syntax(errors) => errors.iter().map(|error| error.render(source)).collect(),
+        }
+    }
+}
 
-    println!("______starting prasing IF");
-    if let Some(Token::If) = tokens.get(0).cloned() {
-        tokens.remove(0);  // Remove `if`
-        let condition = parse_expression(tokens)?;
-        if let Some(Token::LeftBrace) = tokens.get(0) {
-            let then_branch = parse_block(tokens)?;
-            let else_branch = if let Some(Token::Else) = tokens.get(0) {
-                tokens.remove(0);  // Remove `else`
-                if let Some(Token::LeftBrace) = tokens.get(0) {
-                    Some(Box::new(parse_block(tokens)?))
-                } else {
-                    println!("Error: `else` block must start with `{{`");
-                    return None;
+impl std::fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseErrors::Lex(errors) => {
+                for error in errors {
+                    writeln!(f, "Error at {}", error)?;
                 }
-            } else {
-                None
-            };
-
-            println!("____ending parsing IF");
-            return Some(ASTNode::If {
-                condition: Box::new(condition),
-                then_branch: Box::new(then_branch),
-                else_branch,
-            });
-        } else {
-            println!("Error: `then` block must start with `{{`.");
-            return None;
+            }
+            ParseErrors::Syntax(errors) => {
+                for error in errors {
+                    writeln!(f, "Error at {}: {}", error.span, error)?;
+                }
+            }
         }
+        Ok(())
     }
-    None
 }
 
-pub fn parse_while(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    println!("____starting parsing While");
+// Parses a complete source text (a file or a REPL line) up to the end.
+pub fn parse_program(source: &str) -> Result<Program, ParseErrors> {
+    let tokens = tokenize(source).map_err(ParseErrors::Lex)?;
+    parse_tokens(TokenStream::new(&tokens).recovering())
+}
 
-    if let Some(Token::While) = tokens.get(0).cloned() {
-        tokens.remove(0);  // Remove `while`
+// `parse_program`, reporting the grammar rules it goes through to `tracer`.
+pub fn parse_program_with_tracer(source: &str, tracer: &mut dyn Tracer) -> Result<Program, ParseErrors> {
+    let tokens = tokenize(source).map_err(ParseErrors::Lex)?;
+    parse_tokens(TokenStream::new(&tokens).with_tracer(tracer).recovering())
+}
 
-        println!("Parsing while condition, current token: {:?}", tokens.get(0));
-        let condition = match parse_expression(tokens) {
-            Some(cond) => cond,
-            None => {
-                println!("Error: Failed to parse while condition.");
-                return None;
-            }
-        };
+// `parse_program` on a `TokenStream` set up by the caller, e.g. with a raised
+// `with_stack_limit`. Call `recovering` on it to get every syntax error.
+pub fn parse_tokens(mut tokens: TokenStream) -> Result<Program, ParseErrors> {
+    let mut statements = Vec::new();
 
-        println!("Parsed while condition: {:?}", condition);
+    while !tokens.is_at_end() {
+        // A stray `;` is an empty statement
+        if let Some(Token::Semicolon) = tokens.peek() {
+            tokens.advance();
+            continue;
+        }
 
-        println!("Parsing while body, expecting `{{`.");
-        let body = match parse_block(tokens) {
-            Some(b) => b,
-            None => {
-                println!("Error: Failed to parse while body.");
-                return None;
-            }
+        let start = tokens.checkpoint();
+        let statement = match parse_statement_or_recover(&mut tokens) {
+            Ok(statement) => statement,
+            Err(error) => return Err(ParseErrors::Syntax(vec![error])),
         };
-
-        println!("____ending parsing While");
-        return Some(ASTNode::While {
-            condition: Box::new(condition),
-            body: Box::new(body),
-        });
+        // `synchronize` stops in front of a `}`, expecting the enclosing block
+        // to close. At the top level there is none: if the statement that just
+        // failed opened a `{`, the `}` closes it and is skipped without another
+        // error. Any other `}` is reported like every stray one
+        if statement.is_none() && tokens.peek() == Some(&Token::RightBrace) && tokens.leaves_brace_open(start) {
+            tokens.advance();
+        }
+        statements.extend(statement);
     }
 
-    println!("Error: Not a 'while' statement.");
-    None
+    // Report every syntax error in the source, not just the first one
+    let errors = tokens.take_errors();
+    if !errors.is_empty() {
+        return Err(ParseErrors::Syntax(errors));
+    }
+    Ok(Program { statements })
 }
 
-pub fn parse_block(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    if let Some(Token::LeftBrace) = tokens.get(0).cloned() {
-        tokens.remove(0); // Remove `{`
-        let mut statements = Vec::new();
-
-        // Parse each statement until we encounter a `}`
-        while let Some(token) = tokens.get(0) {
-            if let Token::RightBrace = token {
-                tokens.remove(0); // Remove `}`
-                return Some(ASTNode::Block(statements));
-            }
+// The one statement grammar used everywhere: at the top level, in blocks,
+// loop bodies and `switch` cases.
+pub fn parse_statement(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.nested(parse_statement_nested)
+}
 
-            // Try parsing a `while` statement first
-            if let Some(statement) = parse_while(tokens) {
-                statements.push(statement);
-            }
-            // Then try parsing an `if` statement
-            else if let Some(statement) = parse_if(tokens) {
-                statements.push(statement);
-            }
-            // Then try parsing an assignment or other expressions
-            else if let Some(statement) = parse_assignment(tokens) {
-                statements.push(statement);
-            }
-            // Optionally, parse other constructs like `for` or `switch` here
-            else if let Some(statement) = parse_for(tokens) {
-                statements.push(statement);
-            } else if let Some(statement) = parse_switch(tokens) {
-                statements.push(statement);
+fn parse_statement_nested(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("statement");
+    match tokens.peek() {
+        Some(Token::Var) => parse_assignment(tokens),
+        Some(Token::If) => parse_if(tokens),
+        Some(Token::While) => parse_while(tokens),
+        Some(Token::For) => parse_for(tokens),
+        Some(Token::Switch) => parse_switch(tokens),
+        Some(Token::LeftBrace) => parse_block(tokens),
+        Some(Token::Fn) => parse_function(tokens),
+        // A labeled loop, `outer: for ...`
+        Some(Token::Identifier(_)) if tokens.peek_nth(1) == Some(&Token::Colon) => match tokens.peek_nth(2) {
+            Some(Token::While) => parse_while(tokens),
+            Some(Token::For) => parse_for(tokens),
+            _ => {
+                tokens.advance();  // Remove the label
+                tokens.advance();  // Remove `:`
+                Err(tokens.unexpected(&[TokenKind::While, TokenKind::For]))
             }
-            // Add other control structures here if needed.
+        },
+        Some(Token::Return) => parse_return(tokens),
+        Some(Token::Break) => parse_break(tokens),
+        Some(Token::Continue) => parse_continue(tokens),
+        Some(Token::Print) => {
+            let print = parse_print(tokens)?;
+            parse_statement_end(tokens)?;
+            Ok(print)
+        }
+        // `x = x - 1;`
+        Some(Token::Identifier(_)) if tokens.peek_nth(1) == Some(&Token::Equal) => parse_assign(tokens),
+        // Everything else is an expression statement, e.g. a call `f(x);`
+        _ => {
+            let expression = parse_expression(tokens)?;
+            parse_statement_end(tokens)?;
+            Ok(expression)
+        }
+    }
+}
 
-            // Optionally, check for a semicolon after each statement
-            if let Some(Token::Semicolon) = tokens.get(0).cloned() {
-                tokens.remove(0); // Remove `;`
-            } else {
-                println!("Error: Expected a semicolon after the statement.");
+// Parses one statement. When recovering, a syntax error is recorded and the
+// rest of the statement skipped, so the caller can go on with the next one.
+fn parse_statement_or_recover(tokens: &mut TokenStream) -> Result<Option<ASTNode>, ParseError> {
+    let start = tokens.checkpoint();
+    match parse_statement(tokens) {
+        Ok(statement) => Ok(Some(statement)),
+        Err(error) if !tokens.recover => Err(error),
+        Err(error) => {
+            tokens.report(error);
+            tokens.synchronize();
+            // A statement that failed on its very first token (e.g. a stray `}`
+            // at the top level) must still be skipped, or we would loop forever
+            if tokens.checkpoint() == start {
+                tokens.advance();
             }
+            Ok(None)
         }
+    }
+}
 
-        println!("Error: Block was not properly closed with `}}`.");
-        return None;
+// Simple statements end with `;`. It may only be left out before a `}` or at
+// the end of the input. Returns the span of the `;` (or of the following token).
+fn parse_statement_end(tokens: &mut TokenStream) -> Result<Span, ParseError> {
+    match tokens.peek() {
+        Some(Token::Semicolon) => Ok(tokens.advance()),
+        Some(Token::RightBrace) | Some(Token::Eof) | None => Ok(tokens.current_span()),
+        Some(_) => Err(tokens.unexpected(&[TokenKind::Semicolon])),
     }
+}
 
-    println!("Error: Block must start with `{{`.");
-    None
+// `case x: statements`
+fn parse_case(tokens: &mut TokenStream) -> Result<(ASTNode, ASTNode), ParseError> {
+    tokens.expect(TokenKind::Case)?; // Remove `case`
+    let case_value = parse_expression(tokens)?;
+    tokens.expect(TokenKind::Colon)?; // Remove `:`
+    Ok((case_value, parse_case_body(tokens)?))
 }
 
-pub fn parse_binary_op(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    let left = parse_primary_expression(tokens)?;
-    if let Some(operator) = tokens.get(0).cloned() {
-        match operator {
-            Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::GreaterThan | Token::LessThan => {
-                tokens.remove(0);  // Remove the operator
-                let right = parse_primary_expression(tokens)?;
-                return Some(ASTNode::BinaryOp {
-                    left: Box::new(left),
-                    operator,
-                    right: Box::new(right),
-                });
-            }
-            _ => return Some(left),
+// `default: statements`
+fn parse_default(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.expect(TokenKind::Default)?; // Remove `default`
+    tokens.expect(TokenKind::Colon)?; // Remove `:`
+    parse_case_body(tokens)
+}
+
+// Records `error` and skips to the next `case` or `default`, or to the `}`
+// that closes the `switch`. Without recovery `error` is simply returned.
+fn recover_in_switch(tokens: &mut TokenStream, error: ParseError) -> Result<(), ParseError> {
+    if !tokens.recover {
+        return Err(error);
+    }
+    tokens.report(error);
+    let mut depth = 0;
+    loop {
+        match tokens.peek() {
+            None | Some(Token::Eof) => break,
+            Some(Token::Case | Token::Default | Token::RightBrace) if depth == 0 => break,
+            Some(Token::LeftBrace) => depth += 1,
+            Some(Token::RightBrace) => depth -= 1,
+            Some(_) => {}
         }
+        tokens.advance();
     }
-    None
+    Ok(())
 }
 
-pub fn parse_primary_expression(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    if let Some(Token::LeftParen) = tokens.get(0).cloned() {
-        tokens.remove(0);  // Remove `(`
-        let expression = parse_expression(tokens);
-        if let Some(Token::RightParen) = tokens.get(0).cloned() {
-            tokens.remove(0);  // Remove `)`
-            return expression;
-        } else {
-            println!("Error: Expected closing paren `)`");
-            return None;
+// The statements after `case x:` or `default:` up to the next `case`, `default` or `}`.
+fn parse_case_body(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    let start = tokens.current_span();
+    let mut statements = Vec::new();
+
+    tokens.switch_depth += 1;  // `break` leaves the `switch`
+    loop {
+        match tokens.peek() {
+            Some(Token::Case | Token::Default | Token::RightBrace | Token::Eof) | None => break,
+            Some(Token::Semicolon) => {
+                tokens.advance();
+            }
+            Some(_) => match parse_statement_or_recover(tokens) {
+                Ok(statement) => statements.extend(statement),
+                Err(error) => {
+                    tokens.switch_depth -= 1;
+                    return Err(error);
+                }
+            },
         }
     }
+    tokens.switch_depth -= 1;
 
-    if let Some(Token::Identifier(var_name)) = tokens.get(0).cloned() {
-        tokens.remove(0);  // Remove identifier
-        return Some(ASTNode::Identifier(var_name));
+    let span = statements.last().map_or(start, |last| start.to(last.span()));
+    Ok(ASTNode::Block(statements, span))
+}
+
+pub fn parse_assignment(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("assignment");
+
+    let start = tokens.expect(TokenKind::Var)?;  // Remove `var`
+
+    let var_name = match tokens.peek() {
+        Some(Token::Identifier(var_name)) => var_name.clone(),
+        _ => return Err(tokens.unexpected(&[TokenKind::Identifier])),
+    };
+    tokens.advance();  // Remove the variable name
+    tokens.expect(TokenKind::Equal)?;  // Remove the equal sign `=`
+
+    // Now parse an expression (e.g., `5 + 3`)
+    let expression = parse_expression(tokens)?;
+
+    // The `;` ends the statement but, like for every other statement, is not part of its span
+    parse_statement_end(tokens)?;
+    let span = start.to(expression.span());
+
+    // Return the declaration AST node
+    Ok(ASTNode::VarDecl {
+        var_name,
+        value: Box::new(expression),
+        span,
+    })
+}
+
+// Binding powers of the infix operators as (left, right); higher binds
+// tighter. A left power lower than the right one makes the operator
+// left-associative, so `1 - 2 - 3` is `(1 - 2) - 3`.
+fn infix_binding_power(operator: &Token) -> Option<(u8, u8)> {
+    match operator {
+        Token::Or => Some((1, 2)),
+        Token::And => Some((3, 4)),
+        Token::EqualEqual | Token::NotEqual => Some((5, 6)),
+        Token::GreaterThan | Token::LessThan | Token::GreaterEqual | Token::LessEqual => Some((7, 8)),
+        Token::Plus | Token::Minus => Some((9, 10)),
+        Token::Multiply | Token::Divide | Token::Modulo => Some((11, 12)),
+        _ => None,
     }
+}
+
+// Prefix operators bind tighter than every infix operator: `!a == b` is `(!a) == b`
+// and `-a * b` is `(-a) * b`.
+const PREFIX_BINDING_POWER: u8 = 13;
+
+// The keyword `not` binds like in Python, looser than comparisons but tighter
+// than `&&` and `||`: `not a == b` is `not (a == b)`, `not a && b` is `(not a) && b`.
+const NOT_KEYWORD_BINDING_POWER: u8 = 5;
+
+// `x = x - 1;`, changing a variable declared before.
+pub fn parse_assign(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("assign");
+    let var_name = match tokens.peek() {
+        Some(Token::Identifier(var_name)) => var_name.clone(),
+        _ => return Err(tokens.unexpected(&[TokenKind::Identifier])),
+    };
+    let name_span = tokens.advance(); // Remove the variable name
+    tokens.expect(TokenKind::Equal)?; // Remove the equal sign `=`
+
+    let right_expr = parse_expression(tokens)?;
+    parse_statement_end(tokens)?;
+    let span = name_span.to(right_expr.span());
+    Ok(ASTNode::Assign {
+        var_name,
+        value: Box::new(right_expr),
+        span,
+    })
+}
+
+pub fn parse_expression(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("expression");
+    parse_expression_bp(tokens, 0)
+}
 
-    if let Some(Token::Number(value)) = tokens.get(0).cloned() {
-        tokens.remove(0);  // Remove number
-        return Some(ASTNode::Number(value));
+// What a `for` loop runs over: any expression, or a range like `1..10`.
+// Ranges only exist here, so `..` has the lowest precedence of all.
+fn parse_iterable(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    let start = parse_expression(tokens)?;
+
+    if let Some(Token::Range) = tokens.peek() {
+        tokens.advance(); // Remove the range operator `..`
+        let end = parse_expression(tokens)?;
+        let span = start.span().to(end.span());
+        return Ok(ASTNode::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            span,
+        });
     }
 
-    println!("Error: No valid primary expression found");
-    None
+    Ok(start)
 }
 
-pub fn parse_binary_op_with_left(tokens: &mut Vec<Token>, left: ASTNode) -> Option<ASTNode> {
-    if let Some(operator) = tokens.get(0).cloned() {
-        match operator {
-            Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::GreaterThan | Token::LessThan => {
-                tokens.remove(0);  // Remove the operator
-                if let Some(right) = parse_primary_expression(tokens) {
-                    return Some(ASTNode::BinaryOp {
-                        left: Box::new(left),
-                        operator,
-                        right: Box::new(right),
-                    });
-                }
+// Precedence climbing: parses a prefix/primary expression, then keeps folding
+// in infix operators as long as they bind at least as tightly as `min_bp`.
+fn parse_expression_bp(tokens: &mut TokenStream, min_bp: u8) -> Result<ASTNode, ParseError> {
+    tokens.nested(|tokens| parse_expression_bp_nested(tokens, min_bp))
+}
+
+fn parse_expression_bp_nested(tokens: &mut TokenStream, min_bp: u8) -> Result<ASTNode, ParseError> {
+    let mut left = match tokens.peek() {
+        // A `-` directly in front of a number is part of the literal, so
+        // `-9223372036854775808` is `i64::MIN` and not the negation of a too large number
+        Some(Token::Minus) if matches!(tokens.peek_nth(1), Some(Token::Number(_) | Token::Float(_))) => {
+            let start = tokens.advance();  // Remove `-`
+            match parse_primary_expression_signed(tokens, true)? {
+                ASTNode::Number(value, span) => ASTNode::Number(value, start.to(span)),
+                ASTNode::Float(value, span) => ASTNode::Float(value, start.to(span)),
+                other => other,
             }
-            _ => {}
         }
+        Some(operator @ (Token::Not | Token::NotKeyword | Token::Minus | Token::Plus)) => {
+            let start = tokens.advance();  // Remove the operator
+            let binding_power = match operator {
+                Token::NotKeyword => NOT_KEYWORD_BINDING_POWER,
+                _ => PREFIX_BINDING_POWER,
+            };
+            let operand = parse_expression_bp(tokens, binding_power)?;
+            let span = start.to(operand.span());
+            ASTNode::UnaryOp { operator: operator.clone(), operand: Box::new(operand), span }
+        }
+        _ => parse_primary_expression(tokens)?,
+    };
+
+    // Calls bind tightest of all: `-f(x)` is `-(f(x))`, and `f(a)(b)` calls the result of `f(a)`
+    while let Some(Token::LeftParen) = tokens.peek() {
+        let open = tokens.advance();  // Remove `(`
+        let args = parse_arguments(tokens, open)?;
+        let span = left.span().to(tokens.previous_span());
+        left = ASTNode::Call { callee: Box::new(left), args, span };
     }
-    Some(left)
-}
-
-fn parse_binary_expression_or_variable(tokens: &mut Vec<Token>, var_name: String) -> Option<ASTNode> {
-    println!("Detected variable or potential binary expression: {}", var_name);
-
-    // If the next token is a binary operator, treat it as a binary expression
-    if let Some(operator) = tokens.get(0).cloned() {
-        match operator {
-            Token::Plus | Token::Minus | Token::Multiply | Token::Divide |
-            Token::GreaterThan | Token::LessThan | Token::GreaterEqual | Token::LessEqual => {
-                tokens.remove(0);  // Remove the operator
-
-                // Parse the right-hand side of the binary operation
-                if let Some(right_expr) = parse_primary_expression(tokens) {
-                    println!("Parsed right-hand side of binary operation: {:?}", right_expr);
-                    return Some(ASTNode::BinaryOp {
-                        left: Box::new(ASTNode::Identifier(var_name)),
-                        operator,
-                        right: Box::new(right_expr),
-                    });
-                } else {
-                    println!("Error: Expected right-hand side expression after operator");
-                    return None;
-                }
-            }
-            _ => {
-                println!("No valid operator found, treating as a simple variable.");
-                return Some(ASTNode::Identifier(var_name));
-            }
+
+    while let Some(operator) = tokens.peek() {
+        let Some((left_bp, right_bp)) = infix_binding_power(operator) else {
+            break;
+        };
+        if left_bp < min_bp {
+            break;
         }
+        tokens.advance();  // Remove the operator
+
+        let right = parse_expression_bp(tokens, right_bp)?;
+        let span = left.span().to(right.span());
+        left = ASTNode::BinaryOp {
+            left: Box::new(left),
+            operator: operator.clone(),
+            right: Box::new(right),
+            span,
+        };
     }
 
-    println!("No operator found, returning simple variable: {}", var_name);
-    Some(ASTNode::Identifier(var_name))
+    Ok(left)
 }
 
-pub fn parse_for(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    if let Some(Token::For) = tokens.get(0).cloned() {
-        tokens.remove(0); // Remove `for`
-        println!("_____Detected for");
-        // Parse the loop variable (iterator)
-        let iterator = match tokens.get(0).cloned() {
-            Some(Token::Identifier(var_name)) => {
-                tokens.remove(0); // Remove the identifier
-                ASTNode::Identifier(var_name) }
-            _ => {
-                println!("Error: Expected identifier in for loop.");
-                return None;
+
+pub fn parse_print(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("print");
+    let start = tokens.expect(TokenKind::Print)?;  // Remove `print`
+
+    // Expect an opening parenthesis `(` after `print`
+    let open = tokens.expect(TokenKind::LeftParen)?;
+
+    // Expect the expression inside the parentheses (e.g., the variable to print)
+    let expression = parse_expression(tokens)?;
+
+    // Expect the closing parenthesis `)`
+    let end = tokens.expect_closing(TokenKind::RightParen, open)?;
+    // The `;` after `print(...)` is handled by `parse_statement`
+    Ok(ASTNode::Print(Box::new(expression), start.to(end)))
+}
+
+pub fn parse_if(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("if");
+    let start = tokens.expect(TokenKind::If)?;  // Remove `if`
+    let condition = parse_expression(tokens)?;
+    let then_branch = parse_block(tokens)?;
+    let else_branch = if let Some(Token::Else) = tokens.peek() {
+        tokens.advance();  // Remove `else`
+        Some(Box::new(parse_block(tokens)?))
+    } else {
+        None
+    };
+
+    let end = else_branch.as_ref().map_or(then_branch.span(), |e| e.span());
+    Ok(ASTNode::If {
+        condition: Box::new(condition),
+        then_branch: Box::new(then_branch),
+        else_branch,
+        span: start.to(end),
+    })
+}
+
+pub fn parse_while(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("while");
+
+    let (label, label_span) = parse_loop_label(tokens);
+    let start = tokens.expect(TokenKind::While)?;  // Remove `while`
+
+    let condition = parse_expression(tokens)?;
+
+    let body = parse_loop_body(tokens, &label)?;
+
+    let span = label_span.unwrap_or(start).to(body.span());
+    Ok(ASTNode::While {
+        condition: Box::new(condition),
+        body: Box::new(body),
+        label,
+        span,
+    })
+}
+
+// When `tokens` is recovering, broken statements inside the block are recorded
+// (see `TokenStream::errors`) and left out of the returned block.
+pub fn parse_block(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("block");
+    let start = tokens.expect(TokenKind::LeftBrace)?; // Remove `{`
+    let mut statements = Vec::new();
+
+    // Parse each statement until we encounter a `}`
+    loop {
+        match tokens.peek() {
+            Some(Token::RightBrace) | Some(Token::Eof) | None => break,
+            // A stray `;` is an empty statement
+            Some(Token::Semicolon) => {
+                tokens.advance();
             }
+            Some(_) => statements.extend(parse_statement_or_recover(tokens)?),
+        }
+    }
+
+    let end = tokens.expect_closing(TokenKind::RightBrace, start)?; // Remove `}`
+    Ok(ASTNode::Block(statements, start.to(end)))
+}
+
+pub fn parse_primary_expression(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    parse_primary_expression_signed(tokens, false)
+}
+
+// `negative` is set when the `-` in front of a number literal was already consumed.
+fn parse_primary_expression_signed(tokens: &mut TokenStream, negative: bool) -> Result<ASTNode, ParseError> {
+    if let Some(Token::LeftParen) = tokens.peek() {
+        let start = tokens.advance();  // Remove `(`
+        let mut expression = parse_expression(tokens)?;
+        let end = tokens.expect_closing(TokenKind::RightParen, start)?;  // Remove `)`
+        // The parentheses belong to the expression, so diagnostics underline them too
+        *expression.span_mut() = start.to(end);
+        return Ok(expression);
+    }
+
+    if let Some(Token::LeftBracket) = tokens.peek() {
+        return parse_list(tokens);
+    }
+
+    if let Some(token @ (Token::True | Token::False)) = tokens.peek() {
+        let span = tokens.advance();  // Remove `true`/`false`
+        return Ok(ASTNode::Bool(*token == Token::True, span));
+    }
+
+    if let Some(Token::Identifier(var_name)) = tokens.peek() {
+        let span = tokens.advance();  // Remove identifier
+        return Ok(ASTNode::Identifier(var_name.clone(), span));
+    }
+
+    if let Some(Token::Number(magnitude)) = tokens.peek() {
+        let value = if negative {
+            0i64.checked_sub_unsigned(*magnitude)
+        } else {
+            i64::try_from(*magnitude).ok()
+        };
+        let Some(value) = value else {
+            return Err(tokens.error(ParseErrorKind::NumberTooLarge, &[]));
         };
-        println!("______Detected Identifier i for example ");
+        let span = tokens.advance();  // Remove number
+        return Ok(ASTNode::Number(value, span));
+    }
+
+    if let Some(Token::Float(value)) = tokens.peek() {
+        let span = tokens.advance();  // Remove float
+        return Ok(ASTNode::Float(if negative { -*value } else { *value }, span));
+    }
+
+    if let Some(Token::Str(text)) = tokens.peek() {
+        let span = tokens.advance();  // Remove string literal
+        return Ok(ASTNode::Str(text.clone(), span));
+    }
+
+    Err(tokens.error(ParseErrorKind::ExpectedExpression, &[]))
+}
 
-        // Expect the `in` keyword
-        if let Some(Token::In) = tokens.get(0).cloned() {
-            tokens.remove(0); // Remove `in`
-            println!("______Detected  in  ");
+pub fn parse_for(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("for");
+    let (label, label_span) = parse_loop_label(tokens);
+    let start = tokens.expect(TokenKind::For)?; // Remove `for`
+    // Parse the loop variable (iterator)
+    let iterator = match tokens.peek() {
+        Some(Token::Identifier(var_name)) => {
+            let span = tokens.advance(); // Remove the identifier
+            ASTNode::Identifier(var_name.clone(), span) }
+        _ => return Err(tokens.unexpected(&[TokenKind::Identifier])),
+    };
+
+    // Expect the `in` keyword
+    tokens.expect(TokenKind::In)?;
+
+    // Parse the iterable (e.g., a range)
+    let iterable = parse_iterable(tokens)?;
+    // Parse the loop body
+    let body = parse_loop_body(tokens, &label)?;
+    // Return the ASTNode for the for loop
+    let span = label_span.unwrap_or(start).to(body.span());
+    Ok(ASTNode::For {
+        iterator: Box::new(iterator),
+        iterable: Box::new(iterable),
+        body: Box::new(body),
+        label,
+        span,
+    })
+}
+// `fn name(a, b) { ... }`
+pub fn parse_function(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("function");
+    let start = tokens.expect(TokenKind::Fn)?;  // Remove `fn`
+
+    let name = match tokens.peek() {
+        Some(Token::Identifier(name)) => name.clone(),
+        _ => return Err(tokens.unexpected(&[TokenKind::Identifier])),
+    };
+    tokens.advance();  // Remove the name
+
+    // Parameter list: identifiers separated by `,`, an empty `()` is fine
+    let open = tokens.expect(TokenKind::LeftParen)?;
+    let mut params = Vec::new();
+    while let Some(Token::Identifier(param)) = tokens.peek() {
+        if params.contains(param) {
+            return Err(tokens.error(ParseErrorKind::DuplicateParameter, &[]));
+        }
+        params.push(param.clone());
+        tokens.advance();  // Remove the parameter
+        if let Some(Token::Comma) = tokens.peek() {
+            tokens.advance();  // Remove `,`
         } else {
-            println!("Error: Expected 'in' in for loop.");
-            return None;
+            break;
         }
+    }
+    tokens.expect_closing(TokenKind::RightParen, open)?;  // Remove `)`
+
+    // Loops and `switch`es around the declaration do not reach into the body
+    let loop_depth = std::mem::take(&mut tokens.loop_depth);
+    let labels = std::mem::take(&mut tokens.labels);
+    let switch_depth = std::mem::take(&mut tokens.switch_depth);
+    tokens.function_depth += 1;
+    let body = parse_block(tokens);
+    tokens.function_depth -= 1;
+    tokens.loop_depth = loop_depth;
+    tokens.labels = labels;
+    tokens.switch_depth = switch_depth;
+    let body = body?;
+
+    let span = start.to(body.span());
+    Ok(ASTNode::Function { name, params, body: Rc::new(body), span })
+}
 
-        // Parse the iterable (e.g., a range)
-        let iterable = parse_expression(tokens)?; // This should handle range expressions like `1..10`
-        println!("___detected 1..10,{:?}", iterable);
-        // Parse the loop body
-        let body = parse_block(tokens)?;
-        println!("____detected block,{:?}",body);
-        // Return the ASTNode for the for loop
-        return Some(ASTNode::For {
-            iterator: Box::new(iterator),
-            iterable: Box::new(iterable),
-            body: Box::new(body),
-        });
+// `return;` or `return expression;`
+pub fn parse_return(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("return");
+    if tokens.function_depth == 0 {
+        return Err(tokens.error(ParseErrorKind::ReturnOutsideFunction, &[]));
     }
+    let start = tokens.expect(TokenKind::Return)?;  // Remove `return`
+
+    let value = match tokens.peek() {
+        Some(Token::Semicolon | Token::RightBrace | Token::Eof) | None => None,
+        _ => Some(Box::new(parse_expression(tokens)?)),
+    };
+    let end = value.as_ref().map_or(start, |value| value.span());
+    parse_statement_end(tokens)?;
+    Ok(ASTNode::Return(value, start.to(end)))
+}
 
-    println!("Error: Not a 'for' loop.");
-    None
+// `break;` leaves the innermost loop or `switch`, `break outer;` the loop labeled `outer`
+pub fn parse_break(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("break");
+    if tokens.loop_depth == 0 && tokens.switch_depth == 0 {
+        return Err(tokens.error(ParseErrorKind::BreakOutsideLoop, &[]));
+    }
+    let start = tokens.expect(TokenKind::Break)?;  // Remove `break`
+    let (label, end) = parse_label_target(tokens, start)?;
+    parse_statement_end(tokens)?;
+    Ok(ASTNode::Break(label, start.to(end)))
 }
-pub fn parse_switch(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    if let Some(Token::Switch) = tokens.get(0).cloned() {
-        tokens.remove(0); // Remove `switch`
-        println!("_____Detected switch");
 
-        // Parse the expression after `switch`
-        let expression = parse_expression(tokens)?;
-        println!("______Parsed switch expression");
+// `continue;` goes on with the next iteration of the innermost loop, `continue outer;`
+// with the one of the loop labeled `outer`
+pub fn parse_continue(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("continue");
+    if tokens.loop_depth == 0 {
+        return Err(tokens.error(ParseErrorKind::ContinueOutsideLoop, &[]));
+    }
+    let start = tokens.expect(TokenKind::Continue)?;  // Remove `continue`
+    let (label, end) = parse_label_target(tokens, start)?;
+    parse_statement_end(tokens)?;
+    Ok(ASTNode::Continue(label, start.to(end)))
+}
 
-        if let Some(Token::LeftBrace) = tokens.get(0).cloned() {
-            tokens.remove(0); // Remove `{`
-        } else {
-            println!("Error: Expected `{{` after `switch` expression");
-            return None;
+// The optional `name:` in front of a loop.
+fn parse_loop_label(tokens: &mut TokenStream) -> (Option<String>, Option<Span>) {
+    match (tokens.peek(), tokens.peek_nth(1)) {
+        (Some(Token::Identifier(label)), Some(Token::Colon)) => {
+            let span = tokens.advance();  // Remove the label
+            tokens.advance();  // Remove `:`
+            (Some(label.clone()), Some(span))
         }
+        _ => (None, None),
+    }
+}
 
-        let mut cases = Vec::new();
-        let mut default_case = None;
-
-        // Parse `case` statements and `default` block
-        while let Some(token) = tokens.get(0).cloned() {
-            match token {
-                Token::Case => {
-                    tokens.remove(0); // Remove `case`
-                    let case_value = parse_expression(tokens)?;
-                    println!("______Parsed case value: {:?}", case_value);
-
-                    if let Some(Token::Colon) = tokens.get(0).cloned() {
-                        tokens.remove(0); // Remove `:`
-                    } else {
-                        println!("Error: Expected `:` after `case` value");
-                        return None;
-                    }
+// The optional label after `break` or `continue`; it has to belong to an
+// enclosing loop. Returns the label and the span the statement ends with.
+fn parse_label_target(tokens: &mut TokenStream, keyword: Span) -> Result<(Option<String>, Span), ParseError> {
+    match tokens.peek() {
+        Some(Token::Identifier(label)) => {
+            if !tokens.labels.contains(label) {
+                return Err(tokens.error(ParseErrorKind::UnknownLabel, &[]));
+            }
+            Ok((Some(label.clone()), tokens.advance()))
+        }
+        _ => Ok((None, keyword)),
+    }
+}
 
-                    // Try to parse a block or a single statement for the `case`
-                    let case_block = if let Some(Token::LeftBrace) = tokens.get(0).cloned() {
-                        parse_block(tokens)?
-                    } else if let Some(statement) = parse_assignment(tokens) {
-                        ASTNode::Block(vec![statement])
-                    } else {
-                        let statement = parse_expression(tokens)?;
-                        ASTNode::Block(vec![statement])
-                    };
-
-                    cases.push((case_value, case_block));
-                }
-                Token::Default => {
-                    tokens.remove(0); // Remove `default`
-
-                    if let Some(Token::Colon) = tokens.get(0).cloned() {
-                        tokens.remove(0); // Remove `:`
-                    } else {
-                        println!("Error: Expected `:` after `default`");
-                        return None;
-                    }
+// The block of a `while` or `for`, where `break` and `continue` are allowed.
+fn parse_loop_body(tokens: &mut TokenStream, label: &Option<String>) -> Result<ASTNode, ParseError> {
+    tokens.loop_depth += 1;
+    tokens.labels.extend(label.clone());
+    let body = parse_block(tokens);
+    if label.is_some() {
+        tokens.labels.pop();
+    }
+    tokens.loop_depth -= 1;
+    body
+}
 
-                    // Try to parse a block or a single statement for the `default`
-                    default_case = Some(Box::new(if let Some(Token::LeftBrace) = tokens.get(0).cloned() {
-                        parse_block(tokens)?
-                    } else if let Some(statement) = parse_assignment(tokens) {
-                        ASTNode::Block(vec![statement])
-                    } else {
-                        let statement = parse_expression(tokens)?;
-                        ASTNode::Block(vec![statement])
-                    }));
-                }
-                Token::RightBrace => {
-                    tokens.remove(0); // Remove `}`
-                    return Some(ASTNode::Switch {
-                        expression: Box::new(expression),
-                        cases,
-                        default: default_case,
-                    });
-                }
-                _ => {
-                    println!("Error: Unexpected token in `switch` block");
-                    return None;
+// The arguments of a call after its `(`, up to and including the `)`.
+fn parse_arguments(tokens: &mut TokenStream, open: Span) -> Result<Vec<ASTNode>, ParseError> {
+    let mut args = Vec::new();
+    loop {
+        match tokens.peek() {
+            Some(Token::RightParen) | Some(Token::Eof) | None => break,
+            _ => {
+                args.push(parse_expression(tokens)?);
+                match tokens.peek() {
+                    Some(Token::Comma) => {
+                        tokens.advance();  // Remove `,`
+                    }
+                    Some(Token::RightParen) | Some(Token::Eof) | None => break,
+                    Some(_) => return Err(tokens.unexpected(&[TokenKind::Comma, TokenKind::RightParen])),
                 }
             }
         }
+    }
+    tokens.expect_closing(TokenKind::RightParen, open)?;  // Remove `)`
+    Ok(args)
+}
 
-        println!("Error: Expected `}}` to close `switch` block");
-        None
-    } else {
-        println!("Error: Not a `switch` statement.");
-        None
+pub fn parse_switch(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("switch");
+    let start = tokens.expect(TokenKind::Switch)?; // Remove `switch`
+
+    // Parse the expression after `switch`
+    let expression = parse_expression(tokens)?;
+
+    let open = tokens.expect(TokenKind::LeftBrace)?; // Remove `{`
+
+    let mut cases = Vec::new();
+    let mut default_case = None;
+
+    // Parse `case` statements and `default` block
+    loop {
+        match tokens.peek() {
+            // Like in a block, a broken case is recorded and skipped so the
+            // rest of the `switch` is still checked
+            Some(Token::Case) => match parse_case(tokens) {
+                Ok(case) => cases.push(case),
+                Err(error) => recover_in_switch(tokens, error)?,
+            },
+            Some(Token::Default) => match parse_default(tokens) {
+                Ok(block) => default_case = Some(Box::new(block)),
+                Err(error) => recover_in_switch(tokens, error)?,
+            },
+            Some(Token::RightBrace) | Some(Token::Eof) | None => {
+                let end = tokens.expect_closing(TokenKind::RightBrace, open)?; // Remove `}`
+                return Ok(ASTNode::Switch {
+                    expression: Box::new(expression),
+                    cases,
+                    default: default_case,
+                    span: start.to(end),
+                });
+            }
+            Some(_) => return Err(tokens.unexpected(&[TokenKind::Case, TokenKind::Default, TokenKind::RightBrace])),
+        }
     }
 }
-pub fn parse_list(tokens: &mut Vec<Token>) -> Option<ASTNode> {
-    if let Some(Token::LeftBracket) = tokens.get(0).cloned() {
-        tokens.remove(0); // Entferne `[`, da wir jetzt eine Liste parsen
+pub fn parse_list(tokens: &mut TokenStream) -> Result<ASTNode, ParseError> {
+    tokens.trace_rule("list");
+    let start = tokens.expect(TokenKind::LeftBracket)?; // Entferne `[`, da wir jetzt eine Liste parsen
 
-        let mut elements = Vec::new();
+    let mut elements = Vec::new();
 
-        while let Some(token) = tokens.get(0).cloned() {
-            match token {
-                Token::RightBracket => {
-                    tokens.remove(0); // Entferne `]` und schließe die Liste ab
-                    return Some(ASTNode::List(elements));
-                }
-                _ => {
-                    // Versuche, jedes Element in der Liste zu parsen
-                    if let Some(element) = parse_expression(tokens) {
-                        elements.push(element);
-
-                        // Überprüfe auf Komma zwischen Listenelementen
-                        if let Some(Token::Comma) = tokens.get(0).cloned() {
-                            tokens.remove(0); // Entferne `,` und gehe zum nächsten Element
-                        } else if let Some(Token::RightBracket) = tokens.get(0).cloned() {
-                            tokens.remove(0); // Schließe die Liste, wenn `]` kommt
-                            return Some(ASTNode::List(elements));
-                        } else {
-                            println!("Error: Erwartetes `,` oder `]` nach Listenelement");
-                            return None;
-                        }
-                    } else {
-                        println!("Error: Konnte Listenelement nicht parsen");
-                        return None;
+    loop {
+        match tokens.peek() {
+            Some(Token::RightBracket) | Some(Token::Eof) | None => break,
+            _ => {
+                // Versuche, jedes Element in der Liste zu parsen
+                elements.push(parse_expression(tokens)?);
+
+                // Überprüfe auf Komma zwischen Listenelementen
+                match tokens.peek() {
+                    Some(Token::Comma) => {
+                        tokens.advance(); // Entferne `,` und gehe zum nächsten Element
                     }
+                    Some(Token::RightBracket) | Some(Token::Eof) | None => break,
+                    Some(_) => return Err(tokens.unexpected(&[TokenKind::Comma, TokenKind::RightBracket])),
                 }
             }
         }
-        println!("Error: Liste wurde nicht mit `]` geschlossen");
-        None
-    } else {
-        println!("Error: Liste muss mit `[` beginnen");
-        None
     }
+
+    let end = tokens.expect_closing(TokenKind::RightBracket, start)?; // Entferne `]` und schließe die Liste ab
+    Ok(ASTNode::List(elements, start.to(end)))
 }
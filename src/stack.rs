@@ -0,0 +1,46 @@
+// The parser and the interpreter recurse once per nested statement and
+// expression. Instead of counting levels and guessing how much stack each one
+// takes, they measure how far the stack has grown since they started and stop
+// with an error before it grows past a limit.
+
+// Default for `TokenStream::with_stack_limit` and `Interpreter::with_stack_limit`:
+// half of the 2 MiB of stack Rust gives a new thread, so with the default a
+// program can never crash the process, whichever thread runs it. The other
+// half is left for the embedder's own frames and for what the parser or the
+// interpreter uses between two checks.
+pub const DEFAULT_STACK_LIMIT: usize = 1024 * 1024;
+
+// How much stack has been used since `base` was taken, the position where
+// parsing or running started.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StackGuard {
+    limit: usize,
+    base: Option<usize>,
+}
+
+impl StackGuard {
+    pub(crate) fn new(limit: usize) -> Self {
+        StackGuard { limit, base: None }
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    // Measures from here on, the outermost level of the recursion.
+    pub(crate) fn start(&mut self) {
+        self.base = Some(position());
+    }
+
+    // Whether the stack grew by more than the limit since `start`.
+    pub(crate) fn exhausted(&self) -> bool {
+        self.base.is_some_and(|base| base.abs_diff(position()) > self.limit)
+    }
+}
+
+// Roughly where the stack ends right now: the address of a local variable.
+#[inline(never)]
+fn position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
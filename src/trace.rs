@@ -0,0 +1,60 @@
+use std::fmt;
+use crate::interpreter::Value;
+use crate::lexer::Span;
+
+// What the parser and interpreter report to a `Tracer` while they work.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent<'a> {
+    // The parser starts on a grammar rule (`"if"`, `"expression"`, ...) at `span`
+    Parse { rule: &'static str, span: Span },
+    // An expression evaluated to `value`
+    Eval { span: Span, value: &'a Value },
+    // A variable was read
+    Lookup { name: &'a str, value: &'a Value },
+    // A variable was set, by `var`, an assignment or a `for` loop
+    Assign { name: &'a str, value: &'a Value },
+}
+
+impl fmt::Display for TraceEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceEvent::Parse { rule, span } => write!(f, "parse {} at {}", rule, span),
+            TraceEvent::Eval { span, value } => write!(f, "eval {} => {}", span, value),
+            TraceEvent::Lookup { name, value } => write!(f, "get {} = {}", name, value),
+            TraceEvent::Assign { name, value } => write!(f, "set {} = {}", name, value),
+        }
+    }
+}
+
+// Receives trace events. Nothing is traced unless a tracer is handed to the
+// parser (`TokenStream::with_tracer`) or the interpreter (`Interpreter::with_tracer`).
+pub trait Tracer {
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+impl fmt::Debug for dyn Tracer + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tracer")
+    }
+}
+
+// Prints every event to stderr, so it does not mix with the program's output.
+pub struct StderrTracer;
+
+impl Tracer for StderrTracer {
+    fn trace(&mut self, event: &TraceEvent) {
+        eprintln!("[trace] {}", event);
+    }
+}
+
+// Keeps the events as text, e.g. to check them in tests.
+#[derive(Debug, Default)]
+pub struct RecordingTracer {
+    pub events: Vec<String>,
+}
+
+impl Tracer for RecordingTracer {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.events.push(event.to_string());
+    }
+}
@@ -1,8 +1,10 @@
 
-    use std::collections::HashMap;
-    use Xene::lexer::{tokenize, Token};
-    use Xene::parser::{parse_assignment, parse_expression, parse_for, parse_if, parse_while,parse_switch,parse_list, ASTNode};
-    use Xene::interpreter::{interpret,evaluate_expression,evaluate_condition};
+    use Xene::lexer::{tokenize, tokenize_with_trivia, LexErrorKind, Span, Token, TokenKind, TriviaKind};
+    use Xene::parser::{parse_assignment, parse_expression, parse_statement, parse_for, parse_if, parse_while,parse_switch,parse_list, parse_block, parse_program, parse_program_with_tracer, parse_tokens, ASTNode, ParseErrorKind, ParseErrors, TokenStream};
+    use Xene::interpreter::{interpret,interpret_program,evaluate_expression,evaluate_condition, Interpreter, OverflowMode, RuntimeErrorKind, Value};
+    use Xene::environment::Environment;
+    use std::rc::Rc;
+    use Xene::trace::RecordingTracer;
 
 
 
@@ -13,70 +15,70 @@
     #[test]
     fn test_tokenize_var_assignment() {
         let input = "var x = 10;";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
         assert_eq!(tokens.len(), 6);  // var, x, =, 10, ; ,eof
     }
 
     #[test]
     fn test_tokenize_binary_expression() {
         let input = "x = x - 1;";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
         assert_eq!(tokens.len(), 7);  // x, =, x, -, 1, ; , eof
     }
 
     #[test]
     fn test_tokenize_condition() {
         let input = "if x >= 10;";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
         assert_eq!(tokens.len(), 6);  // if, x, >=, 10, ;, Eof
 
-        assert_eq!(tokens[0], Token::If);
-        assert!(matches!(tokens[1], Token::Identifier(_)));
-        assert_eq!(tokens[2], Token::GreaterEqual);
-        assert!(matches!(tokens[3], Token::Number(10)));
-        assert_eq!(tokens[4], Token::Semicolon);
-        assert_eq!(tokens[5], Token::Eof);
+        assert_eq!(tokens[0].token, Token::If);
+        assert!(matches!(tokens[1].token, Token::Identifier(_)));
+        assert_eq!(tokens[2].token, Token::GreaterEqual);
+        assert!(matches!(tokens[3].token, Token::Number(10)));
+        assert_eq!(tokens[4].token, Token::Semicolon);
+        assert_eq!(tokens[5].token, Token::Eof);
     }
     #[test]
     fn test_tokenize_multiple_statements() {
         let input = "var x = 5; x = x + 1;";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
         assert_eq!(tokens.len(), 12);  // var, x, =, 5, ;, x, =, x, +, 1, ;, Eof
 
-        assert_eq!(tokens[0], Token::Var);
-        assert!(matches!(tokens[1], Token::Identifier(_)));
-        assert_eq!(tokens[2], Token::Equal);
-        assert!(matches!(tokens[3], Token::Number(5)));
-        assert_eq!(tokens[4], Token::Semicolon);
-
-        assert!(matches!(tokens[5], Token::Identifier(_)));
-        assert_eq!(tokens[6], Token::Equal);
-        assert!(matches!(tokens[7], Token::Identifier(_)));
-        assert_eq!(tokens[8], Token::Plus);
-        assert!(matches!(tokens[9], Token::Number(1)));
-        assert_eq!(tokens[10], Token::Semicolon);
+        assert_eq!(tokens[0].token, Token::Var);
+        assert!(matches!(tokens[1].token, Token::Identifier(_)));
+        assert_eq!(tokens[2].token, Token::Equal);
+        assert!(matches!(tokens[3].token, Token::Number(5)));
+        assert_eq!(tokens[4].token, Token::Semicolon);
+
+        assert!(matches!(tokens[5].token, Token::Identifier(_)));
+        assert_eq!(tokens[6].token, Token::Equal);
+        assert!(matches!(tokens[7].token, Token::Identifier(_)));
+        assert_eq!(tokens[8].token, Token::Plus);
+        assert!(matches!(tokens[9].token, Token::Number(1)));
+        assert_eq!(tokens[10].token, Token::Semicolon);
     }
 
     #[test]
     fn test_tokenize_with_braces_and_parens() {
         let input = "if (x > 5) { print(x); }";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
         assert_eq!(tokens.len(), 14);  // if, (, x, >, 5, ), {, print, (, x, ), ;, }
 
-        assert_eq!(tokens[0], Token::If);                      // if
-        assert_eq!(tokens[1], Token::LeftParen);               // (
-        assert!(matches!(tokens[2], Token::Identifier(_)));     // x
-        assert_eq!(tokens[3], Token::GreaterThan);             // >
-        assert!(matches!(tokens[4], Token::Number(5)));         // 5
-        assert_eq!(tokens[5], Token::RightParen);              // )
-        assert_eq!(tokens[6], Token::LeftBrace);               // {
-        assert_eq!(tokens[7], Token::Print);                   // print
-        assert_eq!(tokens[8], Token::LeftParen);               // (
-        assert!(matches!(tokens[9], Token::Identifier(_)));     // x
-        assert_eq!(tokens[10], Token::RightParen);             // )
-        assert_eq!(tokens[11], Token::Semicolon);               //;
-        assert_eq!(tokens[12], Token::RightBrace);             // }
-        assert!(matches!(tokens[13], Token::Eof));
+        assert_eq!(tokens[0].token, Token::If);                      // if
+        assert_eq!(tokens[1].token, Token::LeftParen);               // (
+        assert!(matches!(tokens[2].token, Token::Identifier(_)));     // x
+        assert_eq!(tokens[3].token, Token::GreaterThan);             // >
+        assert!(matches!(tokens[4].token, Token::Number(5)));         // 5
+        assert_eq!(tokens[5].token, Token::RightParen);              // )
+        assert_eq!(tokens[6].token, Token::LeftBrace);               // {
+        assert_eq!(tokens[7].token, Token::Print);                   // print
+        assert_eq!(tokens[8].token, Token::LeftParen);               // (
+        assert!(matches!(tokens[9].token, Token::Identifier(_)));     // x
+        assert_eq!(tokens[10].token, Token::RightParen);             // )
+        assert_eq!(tokens[11].token, Token::Semicolon);               //;
+        assert_eq!(tokens[12].token, Token::RightBrace);             // }
+        assert!(matches!(tokens[13].token, Token::Eof));
     }
 
 
@@ -85,30 +87,31 @@
     #[test]
     fn test_tokenize_for_loop() {
         let input = "for i in 0..10 { print(i); }";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
 
         assert_eq!(tokens.len(), 14); // for, i, in, 0, .., 10, {, print, (, i, ), ;, }
 
-        assert_eq!(tokens[0], Token::For); // for
-        assert!(matches!(tokens[1], Token::Identifier(_))); // i
-        assert_eq!(tokens[2], Token::In); // in
-        assert!(matches!(tokens[3], Token::Number(0))); // 0
-        assert_eq!(tokens[4], Token::Range); // ..
-        assert!(matches!(tokens[5], Token::Number(10))); // 10
-        assert_eq!(tokens[6], Token::LeftBrace); // {
-        assert_eq!(tokens[7], Token::Print); // print
-        assert_eq!(tokens[8], Token::LeftParen); // (
-        assert!(matches!(tokens[9], Token::Identifier(_))); // i
-        assert_eq!(tokens[10], Token::RightParen); // )
-        assert_eq!(tokens[11], Token::Semicolon); // ;
-        assert_eq!(tokens[12], Token::RightBrace); // }
+        assert_eq!(tokens[0].token, Token::For); // for
+        assert!(matches!(tokens[1].token, Token::Identifier(_))); // i
+        assert_eq!(tokens[2].token, Token::In); // in
+        assert!(matches!(tokens[3].token, Token::Number(0))); // 0
+        assert_eq!(tokens[4].token, Token::Range); // ..
+        assert!(matches!(tokens[5].token, Token::Number(10))); // 10
+        assert_eq!(tokens[6].token, Token::LeftBrace); // {
+        assert_eq!(tokens[7].token, Token::Print); // print
+        assert_eq!(tokens[8].token, Token::LeftParen); // (
+        assert!(matches!(tokens[9].token, Token::Identifier(_))); // i
+        assert_eq!(tokens[10].token, Token::RightParen); // )
+        assert_eq!(tokens[11].token, Token::Semicolon); // ;
+        assert_eq!(tokens[12].token, Token::RightBrace); // }
     }
     #[test]
     fn test_tokenize_switch() {
         let input = "switch x { case 1: print(1); case 2: print(2); default: print(0); }";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let kinds: Vec<Token> = tokens.into_iter().map(|t| t.token).collect();
         assert_eq!(
-            tokens,
+            kinds,
             vec![
                 Token::Switch,
                 Token::Identifier("x".to_string()),
@@ -142,6 +145,239 @@
         );
     }
 
+    #[test]
+    fn test_tokenize_reports_all_errors() {
+        let input = "var x = 1 . 2;\nx = x # 3;";
+        let errors = tokenize(input).unwrap_err();
+        assert_eq!(errors.len(), 2);
+
+        assert_eq!(errors[0].kind, LexErrorKind::LoneDot);
+        assert_eq!(errors[0].character, '.');
+        assert_eq!(errors[0].span, Span { start: 10, end: 11, line: 1, col: 11 });
+        assert_eq!(errors[0].message(), "unexpected single `.` (did you mean `..`?)");
+
+        assert_eq!(errors[1].kind, LexErrorKind::UnknownCharacter);
+        assert_eq!(errors[1].character, '#');
+        assert_eq!((errors[1].span.line, errors[1].span.col), (2, 7));
+        assert_eq!(errors[1].to_string(), "2:7: unknown character `#`");
+    }
+
+    #[test]
+    fn test_tokenize_number_too_large() {
+        let errors = tokenize("var x = 99999999999999999999;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::NumberTooLarge);
+        assert_eq!(errors[0].span.end - errors[0].span.start, 20);
+
+        let errors = tokenize("var x = 1e400;").unwrap_err();
+        assert_eq!(errors[0].message(), "number literal `1e400` is too large");
+    }
+
+    #[test]
+    fn test_tokenize_string_escapes() {
+        let input = r#"print("a\tb\n\"q\" \\ \u{48}\u{1F600}");"#;
+        let tokens = tokenize(input).unwrap();
+        assert_eq!(tokens[2].token, Token::Str("a\tb\n\"q\" \\ H\u{1F600}".to_string()));
+        assert_eq!(tokens[3].token, Token::RightParen);
+    }
+
+    #[test]
+    fn test_tokenize_string_errors() {
+        let errors = tokenize(r#"var s = "bad \q escape";"#).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::InvalidEscape);
+        assert_eq!(errors[0].span.col, 14);
+        assert_eq!(errors[0].message(), "invalid escape sequence `\\q`");
+
+        let errors = tokenize(r#"var s = "\u{110000}" + "\u{41";"#).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.kind == LexErrorKind::InvalidEscape));
+
+        let errors = tokenize("var s = \"never closed;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedString);
+        assert_eq!(errors[0].span.col, 9);
+        assert_eq!(errors[0].message(), "unterminated string literal");
+    }
+
+    #[test]
+    fn test_tokenize_identifiers() {
+        let input = "var var1 = total_sum + _tmp2; variable; in2 9lives";
+        let tokens = tokenize(input).unwrap();
+        let kinds: Vec<Token> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Token::Var,
+                Token::Identifier("var1".to_string()),
+                Token::Equal,
+                Token::Identifier("total_sum".to_string()),
+                Token::Plus,
+                Token::Identifier("_tmp2".to_string()),
+                Token::Semicolon,
+                // Keywords are only recognized as whole words
+                Token::Identifier("variable".to_string()),
+                Token::Semicolon,
+                Token::Identifier("in2".to_string()),
+                // A digit can't start an identifier
+                Token::Number(9),
+                Token::Identifier("lives".to_string()),
+                Token::Eof,
+            ]
+        );
+
+        // Letters may be any Unicode letter, digits only ASCII ones
+        let tokens = tokenize("größe2").unwrap();
+        assert_eq!(tokens[0].token, Token::Identifier("größe2".to_string()));
+        for input in ["x٣", "a²"] {
+            let errors = tokenize(input).unwrap_err();
+            assert_eq!(errors[0].kind, LexErrorKind::UnknownCharacter, "{}", input);
+            assert_eq!(errors[0].span.col, 2, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_floats() {
+        let input = "1.5 2e-3 1E10 3.0e+2 0..10 2else";
+        let tokens = tokenize(input).unwrap();
+        let kinds: Vec<Token> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Token::Float(1.5),
+                Token::Float(0.002),
+                Token::Float(1e10),
+                Token::Float(300.0),
+                // `..` directly after a number is still a range
+                Token::Number(0),
+                Token::Range,
+                Token::Number(10),
+                // `e` without exponent digits is not part of the number
+                Token::Number(2),
+                Token::Else,
+                Token::Eof,
+            ]
+        );
+
+        let errors = tokenize("var x = 1e999;").unwrap_err();
+        assert_eq!(errors[0].kind, LexErrorKind::NumberTooLarge);
+    }
+
+    #[test]
+    fn test_tokenize_booleans_and_logic() {
+        let input = "true && !false || x and not y or z";
+        let kinds: Vec<Token> = tokenize(input).unwrap().into_iter().map(|t| t.token).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Token::True,
+                Token::And,
+                Token::Not,
+                Token::False,
+                Token::Or,
+                Token::Identifier("x".to_string()),
+                Token::And,
+                Token::NotKeyword,
+                Token::Identifier("y".to_string()),
+                Token::Or,
+                Token::Identifier("z".to_string()),
+                Token::Eof,
+            ]
+        );
+
+        let errors = tokenize("a & b | c").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.kind == LexErrorKind::UnknownCharacter));
+    }
+
+    #[test]
+    fn test_tokenize_equality() {
+        let input = "x == 3 != y = !z";
+        let kinds: Vec<Token> = tokenize(input).unwrap().into_iter().map(|t| t.token).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Token::Identifier("x".to_string()),
+                Token::EqualEqual,
+                Token::Number(3),
+                Token::NotEqual,
+                Token::Identifier("y".to_string()),
+                Token::Equal,
+                Token::Not,
+                Token::Identifier("z".to_string()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let input = "var x = 10; // zehn\n/* outer /* inner */\n still comment */ x = x / 2;";
+        let tokens = tokenize(input).unwrap();
+        let kinds: Vec<Token> = tokens.iter().map(|t| t.token.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Token::Var,
+                Token::Identifier("x".to_string()),
+                Token::Equal,
+                Token::Number(10),
+                Token::Semicolon,
+                Token::Identifier("x".to_string()),
+                Token::Equal,
+                Token::Identifier("x".to_string()),
+                Token::Divide,
+                Token::Number(2),
+                Token::Semicolon,
+                Token::Eof,
+            ]
+        );
+        // Line counting continues through the block comment
+        assert_eq!((tokens[5].span.line, tokens[5].span.col), (3, 19));
+    }
+
+    #[test]
+    fn test_tokenize_preserves_trivia() {
+        let input = "// header\nvar x = 1; /* a /* b */ c */";
+        let (tokens, trivia) = tokenize_with_trivia(input).unwrap();
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(trivia.len(), 2);
+
+        assert_eq!(trivia[0].kind, TriviaKind::LineComment);
+        assert_eq!(trivia[0].text, "// header");
+        assert_eq!(trivia[0].span.line, 1);
+
+        assert_eq!(trivia[1].kind, TriviaKind::BlockComment);
+        assert_eq!(trivia[1].text, "/* a /* b */ c */");
+        assert_eq!((trivia[1].span.line, trivia[1].span.col), (2, 12));
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_block_comment() {
+        let errors = tokenize("var x = 1; /* /* */ oops").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedComment);
+        assert_eq!(errors[0].span.col, 12);
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let input = "var x = 10;\nx = x >= 2;";
+        let tokens = tokenize(input).unwrap();
+
+        // `var` at the very start
+        assert_eq!(tokens[0].span, Span { start: 0, end: 3, line: 1, col: 1 });
+        // `10` spans two bytes
+        assert_eq!(tokens[3].span, Span { start: 8, end: 10, line: 1, col: 9 });
+        // `x` on the second line
+        assert_eq!(tokens[5].span, Span { start: 12, end: 13, line: 2, col: 1 });
+        // `>=` is a single two-character token
+        assert_eq!(tokens[8].token, Token::GreaterEqual);
+        assert_eq!(tokens[8].span, Span { start: 18, end: 20, line: 2, col: 7 });
+        // Eof points at the end of the input
+        assert_eq!(tokens.last().unwrap().span.start, input.len());
+    }
+
 
 
 
@@ -166,45 +402,48 @@
    #[test]
    fn test_parse_assignment() {
        let input = "var x = 10;";
-       let mut tokens = tokenize(input);
+       let tokens = tokenize(input).unwrap();
+       let mut tokens = TokenStream::new(&tokens);
        let ast = parse_assignment(&mut tokens);
-       assert!(ast.is_some());
+       assert!(ast.is_ok());
    }
 
     #[test]
     fn test_parse_expression() {
         let input = "x = x - 1;";
-        let mut tokens = tokenize(input);
-        let ast = parse_expression(&mut tokens);
-        assert!(ast.is_some());
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let ast = parse_statement(&mut tokens);
+        assert!(ast.is_ok());
     }
     #[test]
 
     fn test_parse_if_else() {
         let input = "if x > 5 { print(x); } else { print(0); }";
-        let mut tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
         let ast = parse_if(&mut tokens);
-        assert!(ast.is_some());
+        assert!(ast.is_ok());
 
-        if let Some(ASTNode::If { condition, then_branch, else_branch }) = ast {
+        if let Ok(ASTNode::If { condition, then_branch, else_branch, .. }) = ast {
             // Ensure the condition is correctly parsed
-            if let ASTNode::BinaryOp { left, operator, right } = *condition {
-                assert!(matches!(*left, ASTNode::Identifier(_)));
+            if let ASTNode::BinaryOp { left, operator, right, .. } = *condition {
+                assert!(matches!(*left, ASTNode::Identifier(..)));
                 assert_eq!(operator, Token::GreaterThan);  // Dereference operator here
-                assert!(matches!(*right, ASTNode::Number(_)));
+                assert!(matches!(*right, ASTNode::Number(..)));
             }
 
             // Ensure the `then` branch is a block containing a `print` statement
-            if let ASTNode::Block(statements) = *then_branch {
-                if let ASTNode::Print(expr) = &statements[0] {
-                    assert!(matches!(**expr, ASTNode::Identifier(_)));
+            if let ASTNode::Block(statements, _) = *then_branch {
+                if let ASTNode::Print(expr, _) = &statements[0] {
+                    assert!(matches!(**expr, ASTNode::Identifier(..)));
                 }
             }
 
             // Ensure the `else` branch is a block containing a `print` statement
-            if let Some(ASTNode::Block(statements)) = else_branch.as_deref() {
-                if let ASTNode::Print(expr) = &statements[0] {
-                    assert!(matches!(**expr, ASTNode::Number(_)));
+            if let Some(ASTNode::Block(statements, _)) = else_branch.as_deref() {
+                if let ASTNode::Print(expr, _) = &statements[0] {
+                    assert!(matches!(**expr, ASTNode::Number(..)));
                 }
             }
         }
@@ -213,25 +452,27 @@
     #[test]
     fn test_parse_while_loop() {
         let input = "while x > 5 { print(x); x = x - 1; }";
-        let mut tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
         let ast = parse_while(&mut tokens);
-        assert!(ast.is_some());
+        assert!(ast.is_ok());
     }
 
     #[test]
     fn test_simple_if_parsing() {
         let input = "if x > 5 { print(x); }";
-        let mut tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
         let ast = parse_if(&mut tokens);
 
         // Ensure that the AST is generated
-        assert!(ast.is_some(), "Expected some AST, but got None");
+        assert!(ast.is_ok(), "Expected some AST, but got an error");
 
-        if let Some(ASTNode::If { condition, then_branch, .. }) = ast {
+        if let Ok(ASTNode::If { condition, then_branch, .. }) = ast {
             // Check if the condition is parsed as `x > 5`
             match *condition {
-                ASTNode::BinaryOp { ref left, ref operator, ref right } => {
-                    if let ASTNode::Identifier(ref name) = **left {
+                ASTNode::BinaryOp { ref left, ref operator, ref right, .. } => {
+                    if let ASTNode::Identifier(ref name, _) = **left {
                         assert_eq!(name, "x", "Expected left operand to be 'x'");
                     } else {
                         panic!("Expected Identifier for left operand");
@@ -239,7 +480,7 @@
 
                     assert_eq!(*operator, Token::GreaterThan, "Expected '>' operator");
 
-                    if let ASTNode::Number(value) = **right {
+                    if let ASTNode::Number(value, _) = **right {
                         assert_eq!(value, 5, "Expected right operand to be '5'");
                     } else {
                         panic!("Expected Number for right operand");
@@ -250,9 +491,9 @@
 
             // Check if the then branch contains the print statement `print(x)`
             match *then_branch {
-                ASTNode::Block(ref statements) => {
-                    if let ASTNode::Print(ref expr) = statements[0] {
-                        if let ASTNode::Identifier(ref name) = **expr {
+                ASTNode::Block(ref statements, _) => {
+                    if let ASTNode::Print(ref expr, _) = statements[0] {
+                        if let ASTNode::Identifier(ref name, _) = **expr {
                             assert_eq!(name, "x", "Expected 'x' inside print statement");
                         } else {
                             panic!("Expected Identifier 'x' in print statement");
@@ -271,23 +512,24 @@
     #[test]
     fn test_while_loop_parsing() {
         let input = "while x > 5 { print(x); x = x - 1; }";
-        let mut tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
         let ast = parse_while(&mut tokens);
 
         // Ensure the AST is successfully created
-        assert!(ast.is_some());
+        assert!(ast.is_ok());
 
-        if let Some(ASTNode::While { condition, body }) = ast {
+        if let Ok(ASTNode::While { condition, body, .. }) = ast {
             // Check the condition is correctly parsed as `x > 5`
             match *condition {
-                ASTNode::BinaryOp { ref left, ref operator, ref right } => {
+                ASTNode::BinaryOp { ref left, ref operator, ref right, .. } => {
                     match **left {
-                        ASTNode::Identifier(ref name) => assert_eq!(name, "x"),
+                        ASTNode::Identifier(ref name, _) => assert_eq!(name, "x"),
                         _ => panic!("Expected Identifier for left operand in condition"),
                     }
                     assert_eq!(*operator, Token::GreaterThan); // Dereference the operator
                     match **right {
-                        ASTNode::Number(value) => assert_eq!(value, 5),
+                        ASTNode::Number(value, _) => assert_eq!(value, 5),
                         _ => panic!("Expected Number 5 for right operand in condition"),
                     }
                 }
@@ -296,13 +538,13 @@
 
             // Check the body contains both `print(x)` and `x = x - 1`
             match *body {
-                ASTNode::Block(ref statements) => {
+                ASTNode::Block(ref statements, _) => {
                     assert_eq!(statements.len(), 2);
 
                     // Check the first statement is `print(x)`
-                    if let ASTNode::Print(ref expr) = statements[0] {
+                    if let ASTNode::Print(ref expr, _) = statements[0] {
                         match **expr {
-                            ASTNode::Identifier(ref name) => assert_eq!(name, "x"),
+                            ASTNode::Identifier(ref name, _) => assert_eq!(name, "x"),
                             _ => panic!("Expected Identifier 'x' in print statement"),
                         }
                     } else {
@@ -310,16 +552,16 @@
                     }
 
                     // Check the second statement is `x = x - 1`
-                    if let ASTNode::Assignment { ref var_name, ref value } = statements[1] {
+                    if let ASTNode::Assign { ref var_name, ref value, .. } = statements[1] {
                         assert_eq!(var_name, "x");
-                        if let ASTNode::BinaryOp { ref left, ref operator, ref right } = **value {
+                        if let ASTNode::BinaryOp { ref left, ref operator, ref right, .. } = **value {
                             match **left {
-                                ASTNode::Identifier(ref name) => assert_eq!(name, "x"),
+                                ASTNode::Identifier(ref name, _) => assert_eq!(name, "x"),
                                 _ => panic!("Expected Identifier 'x' in assignment"),
                             }
                             assert_eq!(*operator, Token::Minus); // Dereference the operator
                             match **right {
-                                ASTNode::Number(value) => assert_eq!(value, 1),
+                                ASTNode::Number(value, _) => assert_eq!(value, 1),
                                 _ => panic!("Expected Number 1 in assignment"),
                             }
                         } else {
@@ -339,14 +581,15 @@
     #[test]
     fn test_parse_for_loop() {
         let input = "for i in 0..10 { print(i); }";
-        let mut tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
         let ast = parse_for(&mut tokens);
         println!("____parsed for");
-        assert!(ast.is_some());
+        assert!(ast.is_ok());
 
-        if let Some(ASTNode::For { iterator, iterable, body }) = ast {
+        if let Ok(ASTNode::For { iterator, iterable, body, .. }) = ast {
             // Check that the iterator is "i"
-            if let ASTNode::Identifier(var_name) = *iterator {
+            if let ASTNode::Identifier(var_name, _) = *iterator {
                 assert_eq!(var_name, "i");
 
             } else {
@@ -355,14 +598,14 @@
             println!("_____checked identifier i");
 
             // Check that the iterable is a range from 0 to 10
-            if let ASTNode::Range { start, end } = *iterable {
-                if let ASTNode::Number(start_value) = *start {
+            if let ASTNode::Range { start, end, .. } = *iterable {
+                if let ASTNode::Number(start_value, _) = *start {
                     assert_eq!(start_value, 0);
                 } else {
                     panic!("Expected start of range to be 0.");
                 }
 
-                if let ASTNode::Number(end_value) = *end {
+                if let ASTNode::Number(end_value, _) = *end {
                     assert_eq!(end_value, 10);
                 } else {
                     panic!("Expected end of range to be 10.");
@@ -373,10 +616,10 @@
             println!("_____checked range start end 0 10");
 
             // Check that the body contains the `print(i)` statement
-            if let ASTNode::Block(statements) = *body {
+            if let ASTNode::Block(statements, _) = *body {
                 assert_eq!(statements.len(), 1);
-                if let ASTNode::Print(ref expr) = statements[0] {
-                    if let ASTNode::Identifier(ref var_name) = **expr {
+                if let ASTNode::Print(ref expr, _) = statements[0] {
+                    if let ASTNode::Identifier(ref var_name, _) = **expr {
                         assert_eq!(var_name, "i");
                     } else {
                         panic!("Expected identifier 'i' in print statement.");
@@ -399,11 +642,12 @@
          case 1: print(1);
          case 2: print(2);
          default: print(0); }";
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_switch(&mut tokens) {
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_switch(&mut tokens) {
             match ast {
-                ASTNode::Switch { expression, cases, default } => {
-                    assert!(matches!(*expression, ASTNode::Identifier(_)));
+                ASTNode::Switch { expression, cases, default, .. } => {
+                    assert!(matches!(*expression, ASTNode::Identifier(..)));
                     assert_eq!(cases.len(), 2);
                     assert!(default.is_some());
                 }
@@ -414,30 +658,492 @@
         }
     }
 
+    #[test]
+    fn test_parse_spans() {
+        let input = "while x > 5 {\n    var y = x - 1;\n}";
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let ast = parse_while(&mut tokens).expect("Failed to parse while loop");
+
+        // The loop covers everything from `while` to the closing `}`
+        assert_eq!(ast.span(), Span { start: 0, end: input.len(), line: 1, col: 1 });
+
+        if let ASTNode::While { condition, .. } = ast {
+            // `x > 5`
+            assert_eq!(condition.span(), Span { start: 6, end: 11, line: 1, col: 7 });
+        } else {
+            panic!("Expected a `While` node.");
+        }
+
+        // Parentheses are part of the expression they enclose
+        let input = "(1 + 2) * 3";
+        let tokens = tokenize(input).unwrap();
+        let ast = parse_expression(&mut TokenStream::new(&tokens)).unwrap();
+        assert_eq!(ast.span(), Span { start: 0, end: 11, line: 1, col: 1 });
+        if let ASTNode::BinaryOp { left, .. } = ast {
+            assert_eq!(left.span(), Span { start: 0, end: 7, line: 1, col: 1 });
+        } else {
+            panic!("Expected a `BinaryOp` node.");
+        }
+    }
+
+    #[test]
+    fn test_parse_logical_precedence() {
+        let input = "a || x > 0 && !done";
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let ast = parse_expression(&mut tokens).unwrap();
+
+        // `&&` binds tighter: a || ((x > 0) && (!done))
+        if let ASTNode::BinaryOp { left, operator: Token::Or, right, .. } = ast {
+            assert!(matches!(*left, ASTNode::Identifier(ref name, _) if name == "a"));
+            if let ASTNode::BinaryOp { left, operator: Token::And, right, .. } = *right {
+                assert!(matches!(*left, ASTNode::BinaryOp { operator: Token::GreaterThan, .. }));
+                assert!(matches!(*right, ASTNode::UnaryOp { operator: Token::Not, .. }));
+            } else {
+                panic!("Expected `&&` on the right of `||`");
+            }
+        } else {
+            panic!("Expected `||` at the top");
+        }
+        assert_eq!(tokens.peek(), Some(&Token::Eof));
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let input = "1 + 2 * 3 - 4";
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let ast = parse_expression(&mut tokens).unwrap();
+        assert_eq!(tokens.peek(), Some(&Token::Eof));  // nothing left dangling
+
+        // (1 + (2 * 3)) - 4
+        if let ASTNode::BinaryOp { left, operator: Token::Minus, right, .. } = ast {
+            assert!(matches!(*right, ASTNode::Number(4, _)));
+            if let ASTNode::BinaryOp { left, operator: Token::Plus, right, .. } = *left {
+                assert!(matches!(*left, ASTNode::Number(1, _)));
+                assert!(matches!(*right, ASTNode::BinaryOp { operator: Token::Multiply, .. }));
+            } else {
+                panic!("Expected `+` on the left of `-`");
+            }
+        } else {
+            panic!("Expected `-` at the top");
+        }
+    }
+
+    #[test]
+    fn test_parse_modulo_precedence() {
+        let tokens = tokenize("x + 10 % 4 * 2 == 0").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let ast = parse_expression(&mut tokens).unwrap();
+        assert_eq!(tokens.peek(), Some(&Token::Eof));
+
+        // (x + ((10 % 4) * 2)) == 0: `%` binds like `*` and is left-associative with it
+        if let ASTNode::BinaryOp { left, operator: Token::EqualEqual, .. } = ast {
+            if let ASTNode::BinaryOp { operator: Token::Plus, right, .. } = *left {
+                if let ASTNode::BinaryOp { left, operator: Token::Multiply, .. } = *right {
+                    assert!(matches!(*left, ASTNode::BinaryOp { operator: Token::Modulo, .. }));
+                } else {
+                    panic!("Expected `*` on the right of `+`");
+                }
+            } else {
+                panic!("Expected `+` on the left of `==`");
+            }
+        } else {
+            panic!("Expected `==` at the top");
+        }
+    }
+
+    #[test]
+    fn test_parse_unary_minus() {
+        let tokens = tokenize("-x * 2 - -3").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let ast = parse_expression(&mut tokens).unwrap();
+
+        // ((-x) * 2) - (-3), with `-3` folded into the literal
+        if let ASTNode::BinaryOp { left, operator: Token::Minus, right, .. } = ast {
+            assert!(matches!(*right, ASTNode::Number(-3, Span { start: 9, end: 11, .. })));
+            if let ASTNode::BinaryOp { left, operator: Token::Multiply, .. } = *left {
+                assert!(matches!(*left, ASTNode::UnaryOp { operator: Token::Minus, .. }));
+            } else {
+                panic!("Expected `*` on the left of `-`");
+            }
+        } else {
+            panic!("Expected `-` at the top");
+        }
+
+        // `i64::MIN` can be written as a literal, but its magnitude alone is too large
+        let tokens = tokenize("-9223372036854775808").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        assert!(matches!(parse_expression(&mut tokens), Ok(ASTNode::Number(i64::MIN, _))));
+        let tokens = tokenize("9223372036854775808").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        assert_eq!(parse_expression(&mut tokens).unwrap_err().kind, ParseErrorKind::NumberTooLarge);
+    }
+
+    #[test]
+    fn test_parse_function() {
+        let program = parse_program("fn add(a, b) { return a + b; }\nprint(add(1, 2 * 3));").unwrap();
+        if let ASTNode::Function { name, params, body, .. } = &program.statements[0] {
+            assert_eq!(name, "add");
+            assert_eq!(params, &["a", "b"]);
+            assert!(matches!(&**body, ASTNode::Block(statements, _) if matches!(statements[0], ASTNode::Return(Some(_), _))));
+        } else {
+            panic!("Expected a function declaration");
+        }
+        if let ASTNode::Print(call, _) = &program.statements[1] {
+            assert!(matches!(&**call, ASTNode::Call { callee, args, .. }
+                if matches!(**callee, ASTNode::Identifier(ref name, _) if name == "add") && args.len() == 2));
+        } else {
+            panic!("Expected a print statement");
+        }
+
+        match parse_program("var x = 1;\nreturn x;") {
+            Err(ParseErrors::Syntax(errors)) => {
+                assert_eq!(errors[0].kind, ParseErrorKind::ReturnOutsideFunction);
+                assert_eq!(errors[0].span.line, 2);
+            }
+            other => panic!("Expected a syntax error, got {:?}", other),
+        }
+
+        match parse_program("fn f(a, b, a) { return a; }") {
+            Err(ParseErrors::Syntax(errors)) => {
+                assert_eq!(errors[0].kind, ParseErrorKind::DuplicateParameter);
+                assert_eq!(errors[0].to_string(), "duplicate parameter `a`");
+                assert_eq!(errors[0].span.col, 12);
+            }
+            other => panic!("Expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_break_continue() {
+        let program = parse_program("while true { if x { continue; } break; } switch x { case 1: break; }").unwrap();
+        if let ASTNode::While { body, .. } = &program.statements[0] {
+            assert!(matches!(&**body, ASTNode::Block(statements, _) if matches!(statements[1], ASTNode::Break(None, _))));
+        } else {
+            panic!("Expected a while loop");
+        }
+
+        // `continue` needs a loop, and a function body is outside of the loop around it
+        let input = "break;\nswitch x { case 1: continue; }\nfor i in 0..3 { fn f() { break; } }";
+        match parse_program(input) {
+            Err(ParseErrors::Syntax(errors)) => {
+                let kinds: Vec<_> = errors.iter().map(|error| (error.kind.clone(), error.span.line)).collect();
+                assert_eq!(kinds, [
+                    (ParseErrorKind::BreakOutsideLoop, 1),
+                    (ParseErrorKind::ContinueOutsideLoop, 2),
+                    (ParseErrorKind::BreakOutsideLoop, 3),
+                ]);
+                assert_eq!(errors[0].to_string(), "`break` outside of a loop or `switch`");
+            }
+            other => panic!("Expected syntax errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_labeled_loops() {
+        let program = parse_program("outer: for i in 0..3 { inner: while true { continue outer; break inner; } }").unwrap();
+        if let ASTNode::For { label, body, span, .. } = &program.statements[0] {
+            assert_eq!(label.as_deref(), Some("outer"));
+            assert_eq!(span.col, 1);  // The span starts at the label
+            assert!(matches!(&**body, ASTNode::Block(statements, _)
+                if matches!(&statements[0], ASTNode::While { label: Some(label), .. } if label == "inner")));
+        } else {
+            panic!("Expected a labeled for loop");
+        }
+
+        // Labels are only known inside their own loop, and not in functions declared there
+        let input = "for i in 0..3 { break inner; }\nouter: while true { fn f() { while true { break outer; } } }\nouter: print(1);";
+        match parse_program(input) {
+            Err(ParseErrors::Syntax(errors)) => {
+                let kinds: Vec<_> = errors.iter().map(|error| (error.kind.clone(), error.span.line)).collect();
+                assert_eq!(kinds, [
+                    (ParseErrorKind::UnknownLabel, 1),
+                    (ParseErrorKind::UnknownLabel, 2),
+                    (ParseErrorKind::UnexpectedToken, 3),
+                ]);
+                assert_eq!(errors[0].to_string(), "no enclosing loop is labeled `inner`");
+                assert_eq!(errors[0].span.col, 23);
+                assert_eq!(errors[2].to_string(), "expected `while` or `for`, found `print`");
+            }
+            other => panic!("Expected syntax errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_statements_are_not_expressions() {
+        // `print`, assignments and ranges cannot be used as values
+        for input in ["var y = print(1);", "print(x = 1);", "if x = 1 { }", "x = x = 3;", "[x = 1];", "var r = 0..3;"] {
+            assert!(matches!(parse_program(input), Err(ParseErrors::Syntax(_))), "{} should not parse", input);
+        }
+        assert!(parse_program("x = 1; print(x); for i in 0..3 { }").is_ok());
+    }
+
+    #[test]
+    fn test_parse_range_with_expressions() {
+        let input = "for i in 0..n + 1 { }";
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ASTNode::For { iterable, .. }) = parse_for(&mut tokens) {
+            let ASTNode::Range { start, end, .. } = *iterable else { panic!("Expected a range expression") };
+            assert!(matches!(*start, ASTNode::Number(0, _)));
+            assert!(matches!(*end, ASTNode::BinaryOp { operator: Token::Plus, .. }));
+        } else {
+            panic!("Expected a range expression");
+        }
+    }
+
+    #[test]
+    fn test_token_stream_backtracking() {
+        let tokens = tokenize("if x > { print(x); } var y = 1;").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+
+        // A failed attempt rewinds to where it started
+        assert!(tokens.attempt(parse_if).is_err());
+        assert_eq!(tokens.peek(), Some(&Token::If));
+
+        // Manual checkpoint and rewind
+        let checkpoint = tokens.checkpoint();
+        tokens.advance();
+        tokens.advance();
+        assert_eq!(tokens.peek(), Some(&Token::GreaterThan));
+        tokens.rewind(checkpoint);
+        assert_eq!(tokens.peek(), Some(&Token::If));
+        assert_eq!(tokens.peek_nth(1), Some(&Token::Identifier("x".to_string())));
+        assert!(!tokens.is_at_end());
+    }
+
+    #[test]
+    fn test_parse_program() {
+        let input = "
+        var x = 3;
+        x = x + 1;
+        print(x);
+        while x > 0 { x = x - 1; }
+        switch x { case 0: print(0); x = 5; default: print(x) }
+        ";
+        let program = parse_program(input).unwrap();
+        assert_eq!(program.statements.len(), 5);
+        assert!(matches!(program.statements[0], ASTNode::VarDecl { .. }));
+        assert!(matches!(program.statements[1], ASTNode::Assign { .. }));
+        assert!(matches!(program.statements[2], ASTNode::Print(..)));
+        assert!(matches!(program.statements[3], ASTNode::While { .. }));
+
+        // A case body is a statement list up to the next `case`
+        if let ASTNode::Switch { cases, default, .. } = &program.statements[4] {
+            assert!(matches!(&cases[0].1, ASTNode::Block(statements, _) if statements.len() == 2));
+            assert!(default.is_some());
+        } else {
+            panic!("Expected a switch statement");
+        }
+    }
+
+    #[test]
+    fn test_parse_program_errors() {
+        // Two expressions without `;` in between
+        match parse_program("print(1) print(2);") {
+            Err(ParseErrors::Syntax(errors)) => assert_eq!(errors[0].span.col, 10),
+            other => panic!("Expected a syntax error, got {:?}", other),
+        }
+        assert!(matches!(parse_program("var s = \"open;"), Err(ParseErrors::Lex(_))));
+        assert!(parse_program("").unwrap().statements.is_empty());
+    }
+
+    #[test]
+    fn test_parse_error_expected_found() {
+        let tokens = tokenize("for 1 in 0..3 { }").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let error = parse_for(&mut tokens).unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(error.expected, vec![TokenKind::Identifier]);
+        assert_eq!(error.found, Token::Number(1));
+        assert_eq!(error.span, Span { start: 4, end: 5, line: 1, col: 5 });
+        assert_eq!(error.to_string(), "expected identifier, found number `1`");
+
+        let tokens = tokenize("x = ;").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let error = parse_statement(&mut tokens).unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::ExpectedExpression);
+        assert_eq!(error.found, Token::Semicolon);
+
+        // An unclosed delimiter is reported at the opening one
+        let tokens = tokenize("[1, 2").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let error = parse_list(&mut tokens).unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::UnclosedDelimiter);
+        assert_eq!(error.expected, vec![TokenKind::RightBracket]);
+        assert_eq!(error.found, Token::Eof);
+        assert_eq!(error.span.col, 1);
+    }
+
+    #[test]
+    fn test_render_parse_error() {
+        let source = "var x = 1;\nprint(x) print(2);";
+        let errors = parse_program(source).unwrap_err();
+        assert_eq!(
+            errors.render(source),
+            "error: expected `;`, found `print`\n --> 2:10\n  |\n2 | print(x) print(2);\n  |          ^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_program_reports_all_errors() {
+        let source = "
+        var x = ;
+        print(x) print(2);
+        while x > 0 {
+            x = x - ;
+            if x { print(x); }
+        }
+        var y = 1;
+        ";
+        match parse_program(source) {
+            Err(ParseErrors::Syntax(errors)) => {
+                let lines: Vec<usize> = errors.iter().map(|error| error.span.line).collect();
+                assert_eq!(lines, vec![2, 3, 5]);
+                assert_eq!(errors[1].expected, vec![TokenKind::Semicolon]);
+            }
+            other => panic!("Expected syntax errors, got {:?}", other),
+        }
+
+        // A stray `}` at the top level is skipped instead of looping on it
+        match parse_program("} print(1); var = 2;") {
+            Err(ParseErrors::Syntax(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("Expected syntax errors, got {:?}", other),
+        }
+        // ... and reported also right after a broken statement that did not open it
+        for source in ["print(1 +); }", "print(1 +) }"] {
+            match parse_program(source) {
+                Err(ParseErrors::Syntax(errors)) => {
+                    let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                    assert_eq!(messages, ["expected an expression, found `)`", "expected an expression, found `}`"], "{}", source);
+                }
+                other => panic!("Expected syntax errors, got {:?}", other),
+            }
+        }
+
+        // A broken case is skipped up to the next case, and the switch's `}` is not reported again
+        match parse_program("switch 1 { case 1 print(1); case 2: x = ; default: print(0); } print(2);") {
+            Err(ParseErrors::Syntax(errors)) => {
+                let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                assert_eq!(messages, ["expected `:`, found `print`", "expected an expression, found `;`"]);
+            }
+            other => panic!("Expected syntax errors, got {:?}", other),
+        }
+        match parse_program("if x { print(1) print(2); } print(3);") {
+            Err(ParseErrors::Syntax(errors)) => assert_eq!(errors.len(), 1),
+            other => panic!("Expected a syntax error, got {:?}", other),
+        }
+
+        // A statement broken in its header ends with its body, so an error
+        // right after it is reported too
+        let sources = [
+            ("if 1 > { print(1); }\nvar y = 2\nprint(y);", vec![1, 3]),  // the missing `;` is found at `print`
+            ("fn f(a, a) { }\nvar y = 2 print(y);", vec![1, 2]),
+            ("while x > { }\nfn g(a, a) { }", vec![1, 2]),
+            ("if 1 > { print(1); } else { print(2); }\nvar y = ;", vec![1, 2]),
+        ];
+        for (source, expected_lines) in sources {
+            match parse_program(source) {
+                Err(ParseErrors::Syntax(errors)) => {
+                    let lines: Vec<usize> = errors.iter().map(|error| error.span.line).collect();
+                    assert_eq!(lines, expected_lines, "{}", source);
+                }
+                other => panic!("Expected syntax errors, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_nesting_limit() {
+        // Moderately nested input still parses, also with the default limit
+        let source = format!("var x = {}1{}; {}{}", "(".repeat(100), ")".repeat(100), "{".repeat(100), "}".repeat(100));
+        assert!(parse_program(&source).is_ok());
+
+        // Far deeper input is an error, not a crash, even on a thread with the
+        // default 2 MiB of stack
+        let sources = [
+            format!("var x = {}1{};", "(".repeat(20_000), ")".repeat(20_000)),
+            format!("var x = {}1;", "-".repeat(100_000)),
+            format!("var x = {}1{};", "[".repeat(20_000), "]".repeat(20_000)),
+            format!("{}{}", "{".repeat(20_000), "}".repeat(20_000)),
+            format!("{}{}", "switch 1 { case 1: ".repeat(20_000), "}".repeat(20_000)),
+        ];
+        let kinds = std::thread::spawn(move || {
+            sources
+                .iter()
+                .map(|source| match parse_program(source) {
+                    Err(ParseErrors::Syntax(errors)) => errors.into_iter().map(|error| error.kind).collect(),
+                    other => panic!("expected a syntax error, got {:?}", other.map(|_| ())),
+                })
+                .collect::<Vec<Vec<ParseErrorKind>>>()
+        })
+        .join()
+        .unwrap();
+        for errors in kinds {
+            assert_eq!(errors, vec![ParseErrorKind::NestingTooDeep]);
+        }
+
+        // The limit is a setting of the token stream
+        let tokens = tokenize(&source).unwrap();
+        match parse_tokens(TokenStream::new(&tokens).with_stack_limit(16 * 1024)) {
+            Err(ParseErrors::Syntax(errors)) => assert_eq!(errors[0].kind, ParseErrorKind::NestingTooDeep),
+            other => panic!("expected a syntax error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_parse_block_recovers() {
+        let tokens = tokenize("{ x = 1 + ; { y = ; z = 2; } print(x); }").unwrap();
+        let mut tokens = TokenStream::new(&tokens).recovering();
+
+        // Broken statements are left out, the rest of the block survives
+        if let Ok(ASTNode::Block(statements, _)) = parse_block(&mut tokens) {
+            assert_eq!(statements.len(), 2);
+            assert!(matches!(&statements[0], ASTNode::Block(inner, _) if inner.len() == 1));
+            assert!(matches!(statements[1], ASTNode::Print(..)));
+        } else {
+            panic!("Expected a block");
+        }
+        assert_eq!(tokens.errors().len(), 2);
+        assert_eq!(tokens.peek(), Some(&Token::Eof));
+
+        // Without recovery the first broken statement is an error, not a shorter body
+        let tokens = tokenize("while x > 5 { x = ; x = x - 1; }").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let error = parse_while(&mut tokens).unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::ExpectedExpression);
+        assert!(tokens.errors().is_empty());
+        let tokens = tokenize("switch x { case 1: y = ; }").unwrap();
+        assert!(parse_switch(&mut TokenStream::new(&tokens)).is_err());
+    }
+
     #[test]
     fn test_parse_list() {
         let input = "[1, 2, 3 + 4, var1]";
-        let mut tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         // Versuche, die Liste zu parsen
-        if let Some(ASTNode::List(elements)) = parse_list(&mut tokens) {
+        if let Ok(ASTNode::List(elements, _)) = parse_list(&mut tokens) {
             assert_eq!(elements.len(), 4); // Überprüfe, ob die Liste 4 Elemente hat
 
             // Überprüfe, ob die Elemente korrekt sind
-            assert_eq!(elements[0], ASTNode::Number(1));
-            assert_eq!(elements[1], ASTNode::Number(2));
+            assert!(matches!(elements[0], ASTNode::Number(1, _)));
+            assert!(matches!(elements[1], ASTNode::Number(2, _)));
 
             // Überprüfe den Ausdruck `3 + 4` als binäre Operation
-            if let ASTNode::BinaryOp { left, operator, right } = &elements[2] {
-                assert_eq!(**left, ASTNode::Number(3));
+            if let ASTNode::BinaryOp { left, operator, right, .. } = &elements[2] {
+                assert!(matches!(**left, ASTNode::Number(3, _)));
                 assert_eq!(operator, &Token::Plus);
-                assert_eq!(**right, ASTNode::Number(4));
+                assert!(matches!(**right, ASTNode::Number(4, _)));
             } else {
                 panic!("Expected a binary operation for the third element.");
             }
 
             // Überprüfe das letzte Element `var1` als Identifier
-            assert_eq!(elements[3], ASTNode::Identifier("var1".to_string()));
+            assert!(matches!(elements[3], ASTNode::Identifier(ref name, _) if name == "var1"));
         } else {
             panic!("Failed to parse list.");
         }
@@ -472,60 +1178,64 @@
     #[test]
     fn test_interpreter_with_assignment() {
         let input = "var x = 10;";
-        let mut tokens = tokenize(input);
-        let mut env = HashMap::new();
-        if let Some(ast) = parse_assignment(&mut tokens) {
-            interpret(ast, &mut env);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let mut env = Environment::new();
+        if let Ok(ast) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
-        assert_eq!(*env.get("x").unwrap(), 10);
+        assert_eq!(env.get("x").unwrap(), Value::Int(10));
     }
 
     #[test]
     fn test_interpreter_with_expression() {
         let input = "x = x - 1;";
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 10);
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_expression(&mut tokens) {
-            interpret(ast, &mut env);
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(10));
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_statement(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
-        assert_eq!(*env.get("x").unwrap(), 9);
+        assert_eq!(env.get("x").unwrap(), Value::Int(9));
     }
 
     #[test]
     fn test_interpreter_if_else() {
         let input = "var x = 4; if x > 5 { print(1); } else { print(0); }";
-        let mut tokens = tokenize(input);
-        let mut env = HashMap::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let mut env = Environment::new();
 
         // Parse and execute the assignment
-        if let Some(ast) = parse_assignment(&mut tokens) {
-            interpret(ast, &mut env);
+        if let Ok(ast) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
 
         // Parse and execute the if-else statement
-        if let Some(ast) = parse_if(&mut tokens) {
-            interpret(ast, &mut env);
+        if let Ok(ast) = parse_if(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
 
         // In this case, since x = 4, the else branch should be taken, printing 0.
-        assert_eq!(env.get("x"), Some(&4));
+        assert_eq!(env.get("x"), Some(Value::Int(4)));
     }
 
 
     #[test]
     fn test_interpreter_while_loop() {
         let input = "var x = 10; while x > 5 { x = x - 1; }";
-        let mut tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         println!("Tokens before assignment parsing: {:?}", tokens);  // Check the token stream before parsing
 
-        let mut env = HashMap::new();
+        let mut env = Environment::new();
 
         // Parse and execute the assignment
-        if let Some(ast) = parse_assignment(&mut tokens) {
+        if let Ok(ast) = parse_assignment(&mut tokens) {
             println!("Interpreting assignment: {:?}", ast);
-            interpret(ast, &mut env);
+            interpret(ast, &mut env).unwrap();
         } else {
             println!("Failed to parse assignment.");  // Debug if assignment parsing fails
         }
@@ -533,9 +1243,9 @@
         println!("Tokens after assignment interpretation: {:?}", tokens);  // Check remaining tokens after assignment
 
         // Parse and execute the while loop
-        if let Some(ast) = parse_while(&mut tokens) {
+        if let Ok(ast) = parse_while(&mut tokens) {
             println!("Interpreting while loop: {:?}", ast);
-            interpret(ast, &mut env);
+            interpret(ast, &mut env).unwrap();
         } else {
             println!("Failed to parse the while loop.");  // Debug if while loop parsing fails
         }
@@ -544,50 +1254,52 @@
         println!("Final value of x in env: {:?}", env.get("x"));
 
         // After the loop, `x` should be 5
-        assert_eq!(env.get("x"), Some(&5));
+        assert_eq!(env.get("x"), Some(Value::Int(5)));
     }
 
     #[test]
     fn test_interpret_for_loop() {
         let input = "var total = 0; for i in 1..4 { total = total + i; }";
-        let mut tokens = tokenize(input);
-        let mut env = HashMap::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let mut env = Environment::new();
 
         // Parse the assignment statement
-        if let Some(ASTNode::Assignment { var_name, value }) = parse_assignment(&mut tokens) {
-            interpret(ASTNode::Assignment { var_name, value }, &mut env);
+        if let Ok(ast @ ASTNode::VarDecl { .. }) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         } else {
             panic!("Expected an assignment statement for 'var total = 0;'");
         }
 
         // Parse the for loop and interpret it
-        if let Some(ast) = parse_for(&mut tokens) {
-            interpret(ast, &mut env);
+        if let Ok(ast) = parse_for(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         } else {
             panic!("Expected a for loop after 'var total = 0;'");
         }
 
         // The expected value of `total` should be 1 + 2 + 3 = 6
-        assert_eq!(*env.get("total").unwrap(), 6);
+        assert_eq!(env.get("total").unwrap(), Value::Int(6));
     }
 
 
     #[test]
     fn test_interpret_list() {
         let input = "[1, 2 * 2, var1, 5 + 3]";
-        let mut env = HashMap::new();
-        env.insert("var1".to_string(), 7);
-        let mut tokens = tokenize(input);
+        let mut env = Environment::new();
+        env.define("var1".to_string(), Value::Int(7));
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         // Parse die Liste
-        if let Some(ASTNode::List(elements)) = parse_list(&mut tokens) {
+        if let Ok(ASTNode::List(elements, _)) = parse_list(&mut tokens) {
             // Teste das Interpretieren der Liste
-            let evaluated_list: Vec<i64> = elements.into_iter()
-                .map(|element| evaluate_expression(element, &mut env))
+            let evaluated_list: Vec<Value> = elements.into_iter()
+                .map(|element| evaluate_expression(element, &mut env).unwrap())
                 .collect();
 
             // Überprüfe die erwarteten Werte der ausgewerteten Liste
-            assert_eq!(evaluated_list, vec![1, 4, 7, 8]);
+            assert_eq!(evaluated_list, vec![Value::Int(1), Value::Int(4), Value::Int(7), Value::Int(8)]);
         } else {
             panic!("Failed to parse list for interpretation.");
         }
@@ -600,34 +1312,37 @@
     #[test]
     fn test_variable_assignment() {
         let input = "var x = 10;";
-        let mut tokens = tokenize(input);
-        let mut env = HashMap::new();
-        if let Some(ast) = parse_assignment(&mut tokens) {
-            interpret(ast, &mut env);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let mut env = Environment::new();
+        if let Ok(ast) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
-        assert_eq!(*env.get("x").unwrap(), 10);
+        assert_eq!(env.get("x").unwrap(), Value::Int(10));
     }
 
     #[test]
     fn test_variable_assignment_with_expression() {
         let input = "x = x - 1;";
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 10); // Initialize x to 10
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_expression(&mut tokens) {
-            interpret(ast, &mut env);
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(10)); // Initialize x to 10
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_statement(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
-        assert_eq!(*env.get("x").unwrap(), 9); // Expect x to be 9 after decrement
+        assert_eq!(env.get("x").unwrap(), Value::Int(9)); // Expect x to be 9 after decrement
     }
 
     #[test]
     fn test_print_statement() {
         let input = "print(x);";
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 10); // Initialize x to 10
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_expression(&mut tokens) {
-            interpret(ast, &mut env);
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(10)); // Initialize x to 10
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_statement(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
         // You would need to manually verify the printed output for now.
     }
@@ -635,22 +1350,24 @@
     #[test]
     fn test_binary_operation() {
         let input = "var x = 5 + 3;";
-        let mut tokens = tokenize(input);
-        let mut env = HashMap::new();
-        if let Some(ast) = parse_assignment(&mut tokens) {
-            interpret(ast, &mut env);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let mut env = Environment::new();
+        if let Ok(ast) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
-        assert_eq!(*env.get("x").unwrap(), 8); // Expect x to be 8 (5 + 3)
+        assert_eq!(env.get("x").unwrap(), Value::Int(8)); // Expect x to be 8 (5 + 3)
     }
 
     #[test]
     fn test_if_else_statement() {
         let input = "if x > 5 { print(x); } else { print(0); }";
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 6); // Initialize x to 6
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_if(&mut tokens) {
-            interpret(ast, &mut env);
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(6)); // Initialize x to 6
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_if(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
         // Manual print verification is needed for the correct output.
     }
@@ -658,38 +1375,43 @@
     #[test]
     fn test_while_loop() {
         let input = "while x > 5 { print(x); x = x - 1; }";
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 10); // Initialize x to 10
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_while(&mut tokens) {
-            interpret(ast, &mut env);
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(10)); // Initialize x to 10
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_while(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
-        assert_eq!(*env.get("x").unwrap(), 5); // Expect x to be 5 after loop
+        assert_eq!(env.get("x").unwrap(), Value::Int(5)); // Expect x to be 5 after loop
     }
 
     #[test]
     fn test_invalid_variable_access() {
         let input = "print(y);";
-        let mut env = HashMap::new();
-        let mut tokens = tokenize(input);
-
-        if let Some(ast) = parse_expression(&mut tokens) {
-            interpret(ast, &mut env);
+        let mut env = Environment::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+
+        if let Ok(ast) = parse_statement(&mut tokens) {
+            // 'y' is undefined, so interpreting fails with an error pointing at it
+            let error = interpret(ast, &mut env).unwrap_err();
+            assert_eq!(error.kind, RuntimeErrorKind::UndefinedVariable);
+            assert_eq!(error.span.col, 7);
+        } else {
+            panic!("Expected a print statement.");
         }
-
-        // In this case, we expect that 'y' is undefined and should have printed an error.
-        // Check if the error message is printed (you can mock or capture output if necessary).
     }
 
 
     #[test]
     fn test_invalid_expression() {
         let input = "x = x + ;";
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 10); // Initialize x to 10
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_expression(&mut tokens) {
-            interpret(ast, &mut env); // This will fail because the expression is invalid
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(10)); // Initialize x to 10
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_statement(&mut tokens) {
+            interpret(ast, &mut env).unwrap(); // This will fail because the expression is invalid
         }
         // You can manually check for the expected failure in this case.
     }
@@ -697,11 +1419,12 @@
     #[test]
     fn test_if_else_condition_false() {
         let input = "if x > 5 { print(x); } else { print(0); }";
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 4); // Initialize x to 4
-        let mut tokens = tokenize(input);
-        if let Some(ast) = parse_if(&mut tokens) {
-            interpret(ast, &mut env);
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(4)); // Initialize x to 4
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_if(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
         // Manual verification for the printed value (0)
     }
@@ -709,23 +1432,669 @@
 
     #[test]
     fn test_interpret_switch() {
-        let input = "var x = 2; switch x { case 1: var result = 1; case 2: var result = 2; default: var result = 0; }";
-        let mut tokens = tokenize(input);
-        let mut env = HashMap::new();
+        let input = "var x = 2; switch x { case 1: result = 1; case 2: result = 2; default: result = 0; }";
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let mut env = Environment::new();
+        env.define("result".to_string(), Value::Nil);  // Case bodies are scopes, so `result` has to exist outside
 
         // Parse the variable assignment
-        if let Some(ASTNode::Assignment { var_name, value }) = parse_assignment(&mut tokens) {
-            interpret(ASTNode::Assignment { var_name, value }, &mut env);
+        if let Ok(ast @ ASTNode::VarDecl { .. }) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
         }
 
         // Parse the switch statement and interpret it
-        if let Some(ast) = parse_switch(&mut tokens) {
-            interpret(ast, &mut env);
+        if let Ok(ast) = parse_switch(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
+        }
+
+        assert_eq!(env.get("result").unwrap(), Value::Int(2)); // Expect `result` to be `2` because `x` is `2`
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        let mut env = Environment::new();
+        for input in ["var a = 1.5 * 2;", "var b = 7 / 2;", "var c = 7 / 2.0;", "var d = 0.1 + 1;"] {
+            let tokens = tokenize(input).unwrap();
+            let mut tokens = TokenStream::new(&tokens);
+            if let Ok(ast) = parse_assignment(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
+            }
+        }
+        assert_eq!(env.get("a").unwrap(), Value::Float(3.0));
+        // Two ints stay an int, one float promotes the whole operation
+        assert_eq!(env.get("b").unwrap(), Value::Int(3));
+        assert_eq!(env.get("c").unwrap(), Value::Float(3.5));
+        assert_eq!(env.get("d").unwrap(), Value::Float(1.1));
+        assert_eq!(env.get("c").unwrap().to_string(), "3.5");
+        assert_eq!(env.get("a").unwrap().to_string(), "3.0");
+    }
+
+    #[test]
+    fn test_float_comparison() {
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Float(2.5));
+        let tokens = tokenize("x > 2").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let condition = parse_expression(&mut tokens).unwrap();
+        assert!(evaluate_condition(condition, &mut env).unwrap());
+
+        let tokens = tokenize("x <= 2.4").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let condition = parse_expression(&mut tokens).unwrap();
+        assert!(!evaluate_condition(condition, &mut env).unwrap());
+
+        // An int and a float compare exactly, also above 2^53 and at the ends of i64
+        let cases = [
+            ("9007199254740993 > 9007199254740992.0", true),
+            ("9007199254740992.0 < 9007199254740993", true),
+            ("9007199254740993 <= 9007199254740992.0", false),
+            ("9007199254740992 >= 9007199254740992.0", true),
+            ("-2 > -2.5", true),
+            ("2 < 2.5", true),
+            ("9223372036854775807 < 9223372036854775808.0", true),
+            ("-9223372036854775808 > -9223372036854777856.0", true),
+            ("1 < 0.0 / 0.0", false),
+            ("1 >= 0.0 / 0.0", false),
+        ];
+        for (input, expected) in cases {
+            let tokens = tokenize(input).unwrap();
+            let mut tokens = TokenStream::new(&tokens);
+            let condition = parse_expression(&mut tokens).unwrap();
+            assert_eq!(evaluate_condition(condition, &mut env).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_store_list_in_variable() {
+        let input = r#"var xs = [1, 2.5, "a", [x]];"#;
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(7));
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
+        }
+        let xs = env.get("xs").unwrap();
+        assert_eq!(
+            xs,
+            Value::List(Rc::new(vec![
+                Value::Int(1),
+                Value::Float(2.5),
+                Value::Str("a".into()),
+                Value::List(Rc::new(vec![Value::Int(7)])),
+            ]))
+        );
+        assert_eq!(xs.to_string(), r#"[1, 2.5, "a", [7]]"#);
+
+        // Reading a variable or nesting a list shares its contents instead of copying them
+        let program = parse_program("var a = [1, 2]; var b = [a, a];").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        let (a, b) = (env.get("a"), env.get("b"));
+        let (Some(Value::List(a)), Some(Value::List(b))) = (&a, &b) else {
+            panic!("`a` and `b` should be lists");
+        };
+        for item in b.iter() {
+            assert!(matches!(item, Value::List(item) if Rc::ptr_eq(item, a)));
+        }
+    }
+
+    #[test]
+    fn test_value_display_and_truthiness() {
+        assert_eq!(Value::Nil.to_string(), "nil");
+
+        assert!(!Value::Nil.is_truthy());
+        assert!(!Value::Str("".into()).is_truthy());
+        assert!(!Value::List(Rc::new(vec![])).is_truthy());
+        assert!(Value::Float(0.5).is_truthy());
+    }
+
+    #[test]
+    fn test_type_mismatch_is_an_error() {
+        let mut env = Environment::new();
+        env.define("s".to_string(), Value::Str("text".into()));
+        let tokens = tokenize("s - 1").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let expr = parse_expression(&mut tokens).unwrap();
+        let error = evaluate_expression(expr, &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::TypeMismatch);
+        assert_eq!(error.message, "cannot apply `-` to str and int");
+    }
+
+    #[test]
+    fn test_comparison_yields_bool() {
+        let mut env = Environment::new();
+        let tokens = tokenize("var big = 10 > 3;").unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
+        }
+        assert_eq!(env.get("big").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(3));
+        env.define("done".to_string(), Value::Bool(false));
+
+        let cases = [
+            ("x > 0 && !done", true),
+            ("x > 5 || done", false),
+            ("not done and x < 5", true),
+            // `not` binds looser than comparisons, `!` tighter
+            ("not 1 == 2", true),
+            ("not x > 5", true),
+            ("not x > 0 || true", true),
+            ("!x == false", true),
+            ("false || true && false", false),
+            ("!(x > 0)", false),
+        ];
+        for (input, expected) in cases {
+            let tokens = tokenize(input).unwrap();
+            let mut tokens = TokenStream::new(&tokens);
+            let expr = parse_expression(&mut tokens).unwrap();
+            assert_eq!(evaluate_expression(expr, &mut env).unwrap(), Value::Bool(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_equality_operators() {
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(3));
+        env.define("s".to_string(), Value::Str("a".into()));
+        env.define("xs".to_string(), Value::List(Rc::new(vec![Value::Int(1), Value::Float(2.0)])));
+
+        let cases = [
+            ("x == 3", true),
+            ("x != 3", false),
+            ("x == 3.0", true),
+            (r#"s == "a""#, true),
+            (r#"x == "3""#, false),
+            ("xs == [1.0, 2]", true),
+            ("xs != [1, 2, 3]", true),
+            ("true == (x > 2)", true),
+            ("x == 3 && s != \"b\"", true),
+            // Exact, so equality stays transitive above 2^53
+            ("9007199254740993 == 9007199254740992.0", false),
+            ("9007199254740992.0 != 9007199254740993", true),
+            ("9007199254740992 == 9007199254740992.0", true),
+            ("9007199254740993 == 9007199254740992", false),
+            ("x == 3.5", false),
+            ("9223372036854775807 == 9223372036854775808.0", false),
+            ("-9223372036854775808 == -9223372036854775808.0", true),
+        ];
+        for (input, expected) in cases {
+            let tokens = tokenize(input).unwrap();
+            let mut tokens = TokenStream::new(&tokens);
+            let expr = parse_expression(&mut tokens).unwrap();
+            assert_eq!(evaluate_expression(expr, &mut env).unwrap(), Value::Bool(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_if_with_equality() {
+        let input = "if x == 3 { hit = 1; } else { hit = 0; }";
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(3));
+        env.define("hit".to_string(), Value::Nil);
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_if(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
+        }
+        assert_eq!(env.get("hit").unwrap(), Value::Int(1));
+    }
+
+    #[test]
+    fn test_evaluate_precedence_and_associativity() {
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Bool(false));
+        let cases = [
+            ("1 + 2 * 3 - 4", Value::Int(3)),
+            ("(1 + 2) * 3", Value::Int(9)),
+            ("10 - 2 - 3", Value::Int(5)),
+            ("24 / 4 / 2", Value::Int(3)),
+            ("1 + 2 < 4 == true", Value::Bool(true)),
+            ("!x == true", Value::Bool(true)),
+            ("2 * 3 > 5 && 1 + 1 == 2", Value::Bool(true)),
+        ];
+        for (input, expected) in cases {
+            let tokens = tokenize(input).unwrap();
+            let mut tokens = TokenStream::new(&tokens);
+            let expr = parse_expression(&mut tokens).unwrap();
+            assert_eq!(evaluate_expression(expr, &mut env).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_tracer_events() {
+        let mut tracer = RecordingTracer::default();
+        let program = parse_program_with_tracer("var x = 1 + 2;\nprint(x);", &mut tracer).unwrap();
+        assert_eq!(tracer.events[..3], ["parse statement at 1:1", "parse assignment at 1:1", "parse expression at 1:9"]);
+
+        tracer.events.clear();
+        let mut env = Environment::new();
+        Interpreter::new().with_tracer(&mut tracer).interpret_program(program, &mut env).unwrap();
+        assert_eq!(
+            tracer.events,
+            ["eval 1:9 => 1", "eval 1:13 => 2", "eval 1:9 => 3", "set x = 3", "get x = 3", "eval 2:7 => 3"]
+        );
+    }
+
+    #[test]
+    fn test_print_output() {
+        let program = parse_program("var x = 2;\nprint(x * 21);\nprint(\"done\");\nprint([1, \"a\"]);").unwrap();
+        let mut output = Vec::new();
+        let mut env = Environment::new();
+        Interpreter::new().with_output(&mut output).interpret_program(program, &mut env).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "42\ndone\n[1, \"a\"]\n");
+
+        // A sink that cannot be written to stops the program
+        let mut full = [0u8; 3];
+        let mut output = &mut full[..];
+        let program = parse_program("print(1);\nprint(1234);\nvar after = 1;").unwrap();
+        let error = Interpreter::new().with_output(&mut output).interpret_program(program, &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::Output);
+        assert_eq!(error.span.line, 2);
+        assert_eq!(env.get("after"), None);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let mut env = Environment::new();
+        let source = "var x = 10;\nvar y = x / (x - 10);";
+        let error = interpret_program(parse_program(source).unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::DivisionByZero);
+        assert_eq!(error.render(source), "error: division by zero\n --> 2:9\n  |\n2 | var y = x / (x - 10);\n  |         ^^^^^^^^^^^^\n");
+        assert_eq!(env.get("y"), None);
+
+        // Float division by zero is not an error
+        let program = parse_program("var z = 1.0 / 0;").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("z"), Some(Value::Float(f64::INFINITY)));
+
+        // `&&` and `||` never evaluate the side that cannot change the result
+        let program = parse_program("var a = false && missing;\nvar b = true || missing;").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("a"), Some(Value::Bool(false)));
+        assert_eq!(env.get("b"), Some(Value::Bool(true)));
+
+        let program = parse_program("for i in 0..\"3\" { }").unwrap();
+        assert_eq!(interpret_program(program, &mut env).unwrap_err().kind, RuntimeErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn test_integer_overflow_modes() {
+        let source = "var max = 9223372036854775807;\nvar a = max + 1;\nvar b = max * (0 - 2);\nvar c = (0 - max - 1) / (0 - 1);";
+        let run = |overflow: OverflowMode| {
+            let mut env = Environment::new();
+            let result = Interpreter::new().with_overflow(overflow).interpret_program(parse_program(source).unwrap(), &mut env);
+            (result, env)
+        };
+
+        // Checked is the default and stops at the first overflow
+        let (result, env) = run(OverflowMode::default());
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::IntegerOverflow);
+        assert_eq!(error.message, "integer overflow in `+` with 9223372036854775807 and 1");
+        assert_eq!(error.span.line, 2);
+        assert_eq!(env.get("a"), None);
+
+        let (result, env) = run(OverflowMode::Wrapping);
+        assert!(result.is_ok());
+        assert_eq!(env.get("a"), Some(Value::Int(i64::MIN)));
+        assert_eq!(env.get("b"), Some(Value::Int(2)));
+        assert_eq!(env.get("c"), Some(Value::Int(i64::MIN)));
+
+        let (result, env) = run(OverflowMode::Saturating);
+        assert!(result.is_ok());
+        assert_eq!(env.get("a"), Some(Value::Int(i64::MAX)));
+        assert_eq!(env.get("b"), Some(Value::Int(i64::MIN)));
+        assert_eq!(env.get("c"), Some(Value::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn test_modulo() {
+        let mut env = Environment::new();
+        let program = parse_program("var a = 7 % 3;\nvar b = (0 - 7) % 3;\nvar c = 7 % (0 - 3);\nvar d = 7.5 % 2;").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("a"), Some(Value::Int(1)));
+        // Truncated remainder: the sign follows the left side
+        assert_eq!(env.get("b"), Some(Value::Int(-1)));
+        assert_eq!(env.get("c"), Some(Value::Int(1)));
+        assert_eq!(env.get("d"), Some(Value::Float(1.5)));
+
+        let error = interpret_program(parse_program("var e = a % (a - 1);").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::DivisionByZero);
+        assert_eq!(error.message, "modulo by zero");
+
+        // The remainder of `i64::MIN % -1` is 0 and fits, so no mode reports an overflow
+        for overflow in [OverflowMode::Checked, OverflowMode::Wrapping, OverflowMode::Saturating] {
+            let mut env = Environment::new();
+            let program = parse_program("var f = -9223372036854775808 % -1;").unwrap();
+            Interpreter::new().with_overflow(overflow).interpret_program(program, &mut env).unwrap();
+            assert_eq!(env.get("f"), Some(Value::Int(0)));
+        }
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        let mut env = Environment::new();
+        let program = parse_program("var x = -5;\nvar y = -x + +2;\nvar z = -(1.5 * 2);\nvar m = -9223372036854775808;").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("x"), Some(Value::Int(-5)));
+        assert_eq!(env.get("y"), Some(Value::Int(7)));
+        assert_eq!(env.get("z"), Some(Value::Float(-3.0)));
+        assert_eq!(env.get("m"), Some(Value::Int(i64::MIN)));
+
+        let error = interpret_program(parse_program("var n = -m;").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::IntegerOverflow);
+        let error = interpret_program(parse_program("var s = -\"text\";").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.message, "cannot apply `-` to str");
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "
+        fn factorial(n) {
+            if n < 2 { return 1; }
+            return n * factorial(n - 1);
+        }
+        fn greet() { print(\"hi\"); }
+        var f = factorial(10);
+        var nothing = greet();
+        ";
+        let mut env = Environment::new();
+        let mut output = Vec::new();
+        Interpreter::new().with_output(&mut output).interpret_program(parse_program(source).unwrap(), &mut env).unwrap();
+        assert_eq!(env.get("f"), Some(Value::Int(3628800)));
+        assert_eq!(env.get("nothing"), Some(Value::Nil));
+        assert_eq!(String::from_utf8(output).unwrap(), "hi\n");
+        // Parameters and locals stay inside the call
+        assert_eq!(env.get("n"), None);
+
+        let error = interpret_program(parse_program("factorial(1, 2);").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::ArityMismatch);
+        assert_eq!(error.message, "function `factorial` expects 1 argument(s), got 2");
+
+        let error = interpret_program(parse_program("f(1);").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.message, "cannot call a value of type int");
+
+        // Assignments to globals inside a function outlive the call
+        let mut env = Environment::new();
+        let program = parse_program("var count = 0; fn inc() { count = count + 1; } inc(); inc();").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("count"), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        // Only function calls count toward the limit, however deeply the
+        // statements and expressions inside them are nested
+        let source = "fn f(n) { if n == 0 { return 0; } if true { { return 1 + (1 * f(n - 1)); } } }\nvar x = f(3);";
+        let mut env = Environment::new();
+        Interpreter::new().with_max_call_depth(4).interpret_program(parse_program(source).unwrap(), &mut env).unwrap();
+        assert_eq!(env.get("x"), Some(Value::Int(3)));
+        let error = Interpreter::new().with_max_call_depth(3).interpret_program(parse_program(source).unwrap(), &mut Environment::new()).unwrap_err();
+        assert_eq!(error.message, "maximum call depth of 3 exceeded in `f`");
+        assert_eq!(error.kind, RuntimeErrorKind::StackOverflow);
+
+        // Endless recursion ends in an error
+        let error = interpret_program(parse_program("fn forever(n) { return forever(n + 1); }\nforever(0);").unwrap(), &mut Environment::new()).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::StackOverflow);
+    }
+
+    #[test]
+    fn test_stack_limit() {
+        // With the defaults, on a thread with the default 2 MiB of stack, the
+        // native stack runs out before `DEFAULT_MAX_CALL_DEPTH` is reached in
+        // a debug build. That ends in an error too, not in a crash
+        let sources = [
+            "fn forever(n) { return forever(n + 1); }\nforever(0);".to_string(),
+            format!("fn f(n) {{ {} return f(n); {} }} f(0);", "if true { ".repeat(25), "}".repeat(25)),
+            format!("fn f(n) {{ {} return f(n); {} }} f(0);", "for i in 0..1 { switch n { case 0: { ".repeat(8), "} } }".repeat(8)),
+            "fn f(n) { while true { for i in 0..1 { switch n { case 0: { if true { { return f(n); } } } } } } } f(0);".to_string(),
+            "fn f(n) { return [1, [f(n)]]; } f(0);".to_string(),
+        ];
+        let kinds = std::thread::spawn(move || {
+            // A lower limit stops expressions nested less deeply
+            let nested = format!("var x = {}1{};", "[".repeat(100), "]".repeat(100));
+            let program = parse_program(&nested).unwrap();
+            let mut kinds = vec![Interpreter::new().with_stack_limit(4 * 1024).interpret_program(program, &mut Environment::new()).unwrap_err().kind];
+            let fifty = "fn f(n) { if n <= 0 { return 0; } return n + f(n - 1); }\nvar sum = f(50);";
+            let mut env = Environment::new();
+            interpret_program(parse_program(fifty).unwrap(), &mut env).unwrap();
+            assert_eq!(env.get("sum"), Some(Value::Int(1275)));
+            for source in &sources {
+                kinds.push(interpret_program(parse_program(source).unwrap(), &mut Environment::new()).unwrap_err().kind);
+            }
+            kinds
+        })
+        .join()
+        .unwrap();
+        assert_eq!(kinds, vec![RuntimeErrorKind::StackOverflow; 6]);
+    }
+
+    #[test]
+    fn test_recursion_with_a_raised_limit() {
+        // Like the binary, run on a thread with more stack and raise both limits
+        let result = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let source = "fn f(n) { if n == 0 { return 0; } return 1 + f(n - 1); }\nvar deep = f(5000);\nvar too_deep = f(5001);";
+                let mut env = Environment::new();
+                let error = Interpreter::new()
+                    .with_max_call_depth(5001)
+                    .with_stack_limit(255 * 1024 * 1024)
+                    .interpret_program(parse_program(source).unwrap(), &mut env)
+                    .unwrap_err();
+                // Values are not `Send`, so only their text leaves the thread
+                (env.get("deep").map(|deep| deep.to_string()), error.message)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(result, (Some("5000".to_string()), "maximum call depth of 5001 exceeded in `f`".to_string()));
+    }
+
+    #[test]
+    fn test_deeply_nested_values() {
+        // Lists nested far deeper than any call could go are compared,
+        // printed and dropped without running out of the 2 MiB of stack of a
+        // new thread
+        let source = "var a = []; var b = []; for i in 0..100000 { a = [a]; b = [b]; } var same = a == b; print(a);";
+        let (same, printed) = std::thread::spawn(move || {
+            let mut env = Environment::new();
+            let mut output = Vec::new();
+            Interpreter::new().with_output(&mut output).interpret_program(parse_program(source).unwrap(), &mut env).unwrap();
+            (env.get("same").map(|same| same.to_string()), String::from_utf8(output).unwrap())
+        })
+        .join()
+        .unwrap();
+        assert_eq!(same, Some("true".to_string()));
+        assert_eq!(printed, format!("{}{}\n", "[".repeat(100_001), "]".repeat(100_001)));
+    }
+
+    #[test]
+    fn test_function_scope() {
+        // A function sees the globals and its parameters, also when it is
+        // declared in a block or in another function, but not the variables
+        // of its caller or of the block around its declaration
+        let source = "
+        var step = 10;
+        fn down(n) { if n == 0 { return 0; } return n + down(n - 1); }
+        fn outer(n) {
+            fn inner(k) { return k * step; }
+            return inner(n) + 1;
+        }
+        {
+            var base = 5;
+            fn add(x) { step = step + 1; return x + step; }
+            print(add(base));
+        }
+        print(down(3));
+        var nested = outer(4);
+        ";
+        let mut env = Environment::new();
+        let mut output = Vec::new();
+        Interpreter::new().with_output(&mut output).interpret_program(parse_program(source).unwrap(), &mut env).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "16\n6\n");
+        assert_eq!(env.get("nested"), Some(Value::Int(45)));
+        assert_eq!(env.get("step"), Some(Value::Int(11)));
+        // Functions declared in a block or a call are gone with it
+        assert_eq!(env.get("add"), None);
+        assert_eq!(env.get("inner"), None);
+
+        let sources = [
+            ("fn f() { return local; } { var local = 1; f(); }", "undefined variable `local`"),
+            ("fn f() { return local; } fn g() { var local = 1; return f(); } g();", "undefined variable `local`"),
+            ("{ var base = 1; fn f() { return base; } f(); }", "undefined variable `base`"),
+            ("{ fn f(n) { if n == 0 { return 0; } return f(n - 1); } f(1); }", "undefined variable `f`"),
+        ];
+        for (source, message) in sources {
+            let error = interpret_program(parse_program(source).unwrap(), &mut Environment::new()).unwrap_err();
+            assert_eq!(error.kind, RuntimeErrorKind::UndefinedVariable, "{}", source);
+            assert_eq!(error.message, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_scopes() {
+        let mut env = Environment::new();
+        let program = parse_program("var i = 100; var total = 0; if true { var inner = 1; total = total + inner; } for i in 0..3 { total = total + i; }").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("inner"), None);  // Gone with its block
+        assert_eq!(env.get("i"), Some(Value::Int(100)));  // The loop variable only shadowed it
+        assert_eq!(env.get("total"), Some(Value::Int(4)));  // Outer variables are updated in place
+
+        // A function sees the globals and its parameters, but not the caller's locals
+        let program = parse_program("var n = 1; fn f(n) { return n + total; } fn g() { return local; } var a = f(10);").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("a"), Some(Value::Int(14)));
+        assert_eq!(env.get("n"), Some(Value::Int(1)));
+        let error = interpret_program(parse_program("if true { var local = 1; g(); }").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.message, "undefined variable `local`");
+
+        assert_eq!(env.get("local"), None);  // The block is left again despite the error
+    }
+
+    #[test]
+    fn test_declaration_and_assignment() {
+        let mut env = Environment::new();
+        let program = parse_program("var x = 1; var seen = 0; if true { var x = 2; seen = x; x = 3; }").unwrap();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("x"), Some(Value::Int(1)));  // Only the inner `x` was changed
+        assert_eq!(env.get("seen"), Some(Value::Int(2)));
+
+        // A typo no longer creates a new variable
+        let error = interpret_program(parse_program("sen = 5;").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::UndefinedVariable);
+        assert_eq!(error.message, "cannot assign to undeclared variable `sen`");
+        assert_eq!(env.get("sen"), None);
+
+        let error = interpret_program(parse_program("var x = 5;").unwrap(), &mut env).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::Redeclaration);
+        assert_eq!(error.message, "variable `x` is already declared in this scope");
+        assert_eq!(error.span.col, 1);
+        assert_eq!(env.get("x"), Some(Value::Int(1)));
+
+        // Function declarations share the names of variables
+        for source in ["fn f() {}\nfn f() {}", "var f = 1;\nfn f() {}", "fn f() {}\nvar f = 1;"] {
+            let error = interpret_program(parse_program(source).unwrap(), &mut Environment::new()).unwrap_err();
+            assert_eq!(error.kind, RuntimeErrorKind::Redeclaration);
+            assert_eq!(error.span.line, 2);
+        }
+        let error = interpret_program(parse_program("var x = 1;\nfn x() {}").unwrap(), &mut Environment::new()).unwrap_err();
+        assert_eq!(error.message, "name `x` is already declared in this scope");
+        // A function may still shadow an outer name
+        interpret_program(parse_program("fn g() {} { fn g() {} }").unwrap(), &mut env).unwrap();
+
+        // Parameters are declared in the same scope as the body's variables
+        let source = "fn f(a) { var a = 2; return a; }\nf(1);";
+        let error = interpret_program(parse_program(source).unwrap(), &mut Environment::new()).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::Redeclaration);
+        assert_eq!(error.message, "variable `a` is already declared in this scope");
+        // A nested block may still shadow one
+        let program = parse_program("fn f(a) { { var a = 2; } return a; }\nvar r = f(1);").unwrap();
+        let mut env = Environment::new();
+        interpret_program(program, &mut env).unwrap();
+        assert_eq!(env.get("r"), Some(Value::Int(1)));
+
+        // Declarations and assignments both end before their `;`
+        let program = parse_program("var x = 1;\nx = 2;").unwrap();
+        assert_eq!(program.statements[0].span(), Span { start: 0, end: 9, line: 1, col: 1 });
+        assert_eq!(program.statements[1].span(), Span { start: 11, end: 16, line: 2, col: 1 });
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let mut env = Environment::new();
+        let input = "
+        var total = 0;
+        for i in 0..10 {
+            if i % 2 == 1 { continue; }
+            if i == 8 { break; }
+            total = total + i;
+        }
+        var n = 0;
+        while true {
+            n = n + 1;
+            switch n { case 3: break; default: continue; }
+            break;
+        }
+        ";
+        interpret_program(parse_program(input).unwrap(), &mut env).unwrap();
+        assert_eq!(env.get("total"), Some(Value::Int(12)));  // 0 + 2 + 4 + 6
+        assert_eq!(env.get("n"), Some(Value::Int(3)));  // `break` in the `switch` only left the `switch`
+    }
+
+    #[test]
+    fn test_labeled_loops() {
+        let mut env = Environment::new();
+        let input = "
+        var found = [];
+        var visited = 0;
+        outer: for y in 0..5 {
+            for x in 0..5 {
+                if x > y { continue outer; }
+                visited = visited + 1;
+                switch x * y { case 6: found = [x, y]; break outer; }
+            }
         }
+        ";
+        interpret_program(parse_program(input).unwrap(), &mut env).unwrap();
+        // Rows 0, 1 and 2 visit 1 + 2 + 3 cells, row 3 stops at (2, 3)
+        assert_eq!(env.get("found"), Some(Value::List(Rc::new(vec![Value::Int(2), Value::Int(3)]))));
+        assert_eq!(env.get("visited"), Some(Value::Int(9)));
+    }
 
-        assert_eq!(*env.get("result").unwrap(), 2); // Expect `result` to be `2` because `x` is `2`
+    #[test]
+    fn test_string_concatenation() {
+        let input = r#"var s = "x = " + x;"#;
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Int(3));
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        if let Ok(ast) = parse_assignment(&mut tokens) {
+            interpret(ast, &mut env).unwrap();
+        }
+        assert_eq!(env.get("s").unwrap(), Value::Str("x = 3".into()));
     }
 
+    #[test]
+    fn test_print_string_literal() {
+        let input = r#"print("hello" + " world");"#;
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
+        let ast = parse_statement(&mut tokens);
+        if let Ok(ASTNode::Print(expr, _)) = ast {
+            let mut env = Environment::new();
+            assert_eq!(evaluate_expression(*expr, &mut env).unwrap(), Value::Str("hello world".into()));
+        } else {
+            panic!("Expected a print statement.");
+        }
+    }
 
 
 
@@ -740,6 +2109,23 @@
 
 
 
+    #[test]
+    fn test_interpret_program() {
+        let input = "
+        var total = 0;
+        for i in 0..4 {
+            if i > 1 { total = total + i; }
+        }
+        var x = 2;
+        while x > 0 { x = x - 1; total = total + 10 }
+        ";
+        let mut env = Environment::new();
+        interpret_program(parse_program(input).unwrap(), &mut env).unwrap();
+        assert_eq!(env.get("total"), Some(Value::Int(25)));
+        assert_eq!(env.get("x"), Some(Value::Int(0)));
+    }
+
+
 
 
     //INTEGRATE TESTS COMPLEX
@@ -757,22 +2143,23 @@
             sum = sum - 2;
         }";
 
-        let mut env = HashMap::new();
-        let mut tokens = tokenize(input);
+        let mut env = Environment::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         // Parse and execute multiple statements
-        while !tokens.is_empty() {
-            if let Some(ast) = parse_assignment(&mut tokens) {
-                interpret(ast, &mut env);
-            } else if let Some(ast) = parse_while(&mut tokens) {
-                interpret(ast, &mut env);
+        while !tokens.is_at_end() {
+            if let Ok(ast) = parse_assignment(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
+            } else if let Ok(ast) = parse_while(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
             } else {
                 break; // Stop if there are no more valid tokens
             }
         }
 
         // After execution, `sum` should be 0, as it decreases by 2 in the loop until it reaches 0
-        assert_eq!(*env.get("sum").unwrap(), 0);
+        assert_eq!(env.get("sum").unwrap(), Value::Int(0));
     }
     #[test]
     fn test_nested_if_else_in_loop() {
@@ -787,17 +2174,13 @@
             x = x - 1;
         }";
 
-        let mut env = HashMap::new();
-        env.insert("x".to_string(), 10);
-        let mut tokens = tokenize(input);
+        let mut env = Environment::new();
 
-        // Parse and execute
-        if let Some(ast) = parse_while(&mut tokens) {
-            interpret(ast, &mut env);
-        }
+        // Parse and execute the declaration and the loop
+        interpret_program(parse_program(input).unwrap(), &mut env).unwrap();
 
         // After the loop, `x` should be 0
-        assert_eq!(*env.get("x").unwrap(), 0);
+        assert_eq!(env.get("x").unwrap(), Value::Int(0));
     }
     #[test]
     fn test_complex_conditionals() {
@@ -812,23 +2195,24 @@
             print(y);  // Should not print, as x > y is true
         }";
 
-        let mut env = HashMap::new();
-        let mut tokens = tokenize(input);
+        let mut env = Environment::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         // Parse and execute
-        while !tokens.is_empty() {
-            if let Some(ast) = parse_assignment(&mut tokens) {
-                interpret(ast, &mut env);
-            } else if let Some(ast) = parse_if(&mut tokens) {
-                interpret(ast, &mut env);
+        while !tokens.is_at_end() {
+            if let Ok(ast) = parse_assignment(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
+            } else if let Ok(ast) = parse_if(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
             } else {
                 break;
             }
         }
 
         // After execution, `x` should be 15, `y` should remain 5
-        assert_eq!(*env.get("x").unwrap(), 15);
-        assert_eq!(*env.get("y").unwrap(), 5);
+        assert_eq!(env.get("x").unwrap(), Value::Int(15));
+        assert_eq!(env.get("y").unwrap(), Value::Int(5));
     }
     #[test]
     fn test_complex_expressions_with_loop_and_condition() {
@@ -844,25 +2228,26 @@
             limit = limit - 1;
         }";
 
-        let mut env = HashMap::new();
-        let mut tokens = tokenize(input);
+        let mut env = Environment::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         // Parse and execute
-        while !tokens.is_empty() {
-            if let Some(ast) = parse_assignment(&mut tokens) {
-                interpret(ast, &mut env);
-            } else if let Some(ast) = parse_while(&mut tokens) {
-                interpret(ast, &mut env);
+        while !tokens.is_at_end() {
+            if let Ok(ast) = parse_assignment(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
+            } else if let Ok(ast) = parse_while(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
             } else {
                 break;
             }
         }
 
         // After execution, `total` should accumulate values based on the condition
-        assert_eq!(*env.get("total").unwrap(), 8);  // Example expected result
+        // 9 + 6 + 3 for the multiples of 3, minus 1 for each of the other 7 values
+        assert_eq!(env.get("total").unwrap(), Value::Int(11));
     }
-    //#[test] unendlcih loop
-
+    #[test]
     fn test_multiple_loops_and_conditionals() {
         let input = "
     var a = 5;
@@ -883,26 +2268,28 @@
     }";
 
 
-        let mut env = HashMap::new();
-        let mut tokens = tokenize(input);
+        let mut env = Environment::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         // Parse and execute
 
-        while !tokens.is_empty() {
-            if let Some(ast) = parse_while(&mut tokens) {
-                interpret(ast, &mut env);
-            } else if let Some(ast) = parse_if(&mut tokens) {
-                interpret(ast, &mut env);
-            } else if let Some(ast) = parse_assignment(&mut tokens) {
-                interpret(ast, &mut env);
+        while !tokens.is_at_end() {
+            if let Ok(ast) = parse_while(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
+            } else if let Ok(ast) = parse_if(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
+            } else if let Ok(ast) = parse_assignment(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
             } else {
                 break;
             }
         }
 
 
-        // After execution, `c` should be calculated based on loop and conditional
-        assert_eq!(*env.get("c").unwrap(), 10);  // Example expected result
+        // 6 and 8 add 2 each, 5, 7 and 9 add 1 each
+        assert_eq!(env.get("c").unwrap(), Value::Int(7));
+        assert_eq!(env.get("a").unwrap(), Value::Int(10));
     }
     #[test]
     fn test_nested_loops() {
@@ -918,20 +2305,21 @@
             i = i + 1;
         }";
 
-        let mut env = HashMap::new();
-        let mut tokens = tokenize(input);
+        let mut env = Environment::new();
+        let tokens = tokenize(input).unwrap();
+        let mut tokens = TokenStream::new(&tokens);
 
         // Parse and execute
-        while !tokens.is_empty() {
-            if let Some(ast) = parse_assignment(&mut tokens) {
-                interpret(ast, &mut env);
-            } else if let Some(ast) = parse_while(&mut tokens) {
-                interpret(ast, &mut env);
+        while !tokens.is_at_end() {
+            if let Ok(ast) = parse_assignment(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
+            } else if let Ok(ast) = parse_while(&mut tokens) {
+                interpret(ast, &mut env).unwrap();
             } else {
                 break;
             }
         }
 
         // After execution, `total` should be 25 (5 * 5)
-        assert_eq!(*env.get("total").unwrap(), 25);
+        assert_eq!(env.get("total").unwrap(), Value::Int(25));
     }